@@ -51,7 +51,7 @@ fn main() {
     app.add_plugins((
         PlayerPlugin,
         CameraPlugin,
-        MapPlugin,
+        MapPlugin::default(),
     ));
 
     app.add_systems(Startup, rapier_setup);
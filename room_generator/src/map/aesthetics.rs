@@ -1,11 +1,17 @@
-use super::room::{self, Room, StructureDimensions};
+use super::{
+    room::{self, Room, StructureDimensions},
+    wfc::OverlappingModel,
+};
 use bevy::prelude::*;
 use dyn_clone::DynClone;
 use grid::Grid;
 use iter_num_tools::lin_space;
+use noise::{NoiseFn, Perlin, Simplex, Worley};
 
 pub trait AesthetiziseRoom: DynClone {
-    fn generate_features(&self, rooms: &mut Room, destructive: bool);
+    /// `doors` are the local positions on the edge of the room where doors will be carved.
+    /// `seed` is derived from the WorldgenRng, for aesthetics that have to be the same for every run
+    fn generate_features(&self, rooms: &mut Room, destructive: bool, doors: &[UVec2], seed: u64);
 }
 dyn_clone::clone_trait_object!(AesthetiziseRoom);
 
@@ -13,15 +19,40 @@ dyn_clone::clone_trait_object!(AesthetiziseRoom);
 pub enum Aesthetics {
     Pillars(Pillars),
     CellularAutomata(CellularAutomata),
+    Carpet(Carpet),
+    Scatter(Scatter),
+    Noise(Noise),
+    Bsp(Bsp),
+    DrunkardsWalk(DrunkardsWalk),
+    Wfc(Wfc),
+    Symmetry(Symmetry),
 }
 
 impl Aesthetics {
-    pub fn generate_features(&self, room: &mut Room, destructive: bool) {
+    pub fn generate_features(
+        &self,
+        room: &mut Room,
+        destructive: bool,
+        doors: &[UVec2],
+        seed: u64,
+    ) {
         match self {
-            Aesthetics::Pillars(x) => x.generate_features(room, destructive),
-            Aesthetics::CellularAutomata(x) => x.generate_features(room, destructive),
+            Aesthetics::Pillars(x) => x.generate_features(room, destructive, doors, seed),
+            Aesthetics::CellularAutomata(x) => x.generate_features(room, destructive, doors, seed),
+            Aesthetics::Carpet(x) => x.generate_features(room, destructive, doors, seed),
+            Aesthetics::Scatter(x) => x.generate_features(room, destructive, doors, seed),
+            Aesthetics::Noise(x) => x.generate_features(room, destructive, doors, seed),
+            Aesthetics::Bsp(x) => x.generate_features(room, destructive, doors, seed),
+            Aesthetics::DrunkardsWalk(x) => x.generate_features(room, destructive, doors, seed),
+            Aesthetics::Wfc(x) => x.generate_features(room, destructive, doors, seed),
+            Aesthetics::Symmetry(x) => x.generate_features(room, destructive, doors, seed),
         }
     }
+
+    /// these run in the DoorAesthetics pass after the doors are carved, all others run before
+    pub fn runs_after_doors(&self) -> bool {
+        matches!(self, Aesthetics::Symmetry(_))
+    }
 }
 
 #[derive(Clone, Debug, serde::Deserialize)]
@@ -42,7 +73,14 @@ pub enum Axis {
 }
 
 impl AesthetiziseRoom for Pillars {
-    fn generate_features(&self, rooms: &mut Room, _destructive: bool) {
+    fn generate_features(
+        &self,
+        rooms: &mut Room,
+        _destructive: bool,
+        _doors: &[UVec2],
+        seed: u64,
+    ) {
+        let mut rng = fastrand::Rng::with_seed(seed);
         let mut pillar_anchors: Vec<UVec2> = Vec::new();
         let mut pillar_tiles: Vec<UVec2> = Vec::new();
 
@@ -57,7 +95,7 @@ impl AesthetiziseRoom for Pillars {
                             evenly_spaced_x.clone().nth(i + 1).unwrap() as u32,
                             evenly_spaced_y
                                 .clone()
-                                .nth(fastrand::usize(0..evenly_spaced_y.len()))
+                                .nth(rng.usize(0..evenly_spaced_y.len()))
                                 .unwrap() as u32,
                         );
                         pillar_anchors.push(pillar_anchor);
@@ -68,7 +106,7 @@ impl AesthetiziseRoom for Pillars {
                         let pillar_anchor = UVec2::new(
                             evenly_spaced_x
                                 .clone()
-                                .nth(fastrand::usize(0..evenly_spaced_x.len()))
+                                .nth(rng.usize(0..evenly_spaced_x.len()))
                                 .unwrap() as u32,
                             evenly_spaced_y.clone().nth(i + 1).unwrap() as u32,
                         );
@@ -101,77 +139,237 @@ impl AesthetiziseRoom for Pillars {
             }
         }
 
-        // poke walls into the room
+        // poke walls into the room, the outer walls stay as they are
         for tile_position in &pillar_tiles {
-            rooms.set_tile(*tile_position, room::Tile::Wall);
+            if !rooms.is_edge_tile(*tile_position) {
+                rooms.set_tile(*tile_position, room::Tile::Wall);
+            }
         }
     }
 }
 
 /// modified version of https://www.roguebasin.com/index.php?title=Cellular_Automata_Method_for_Generating_Random_Cave-Like_Levels
+/// without a `schedule` the roguebasin rule runs `iterations` times, with one every step of the schedule runs its own rule
 #[derive(Clone, Debug, serde::Deserialize)]
 pub struct CellularAutomata {
+    #[serde(default)]
     pub iterations: usize,
     pub wall_percentage: f32,
+    #[serde(default)]
+    pub schedule: Vec<CellularAutomataStep>,
+    #[serde(default = "default_clear_corridor")]
+    pub clear_corridor: Option<ClearCorridor>, // None fills every tile randomly
+}
+
+/// runs `rule` `iterations` times. walls are the living cells
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct CellularAutomataStep {
+    // birth/survival in B/S notation, e.g. "B5678/S45678". counts above 9 are separated by commas and can be ranges, e.g. "B13-24/S12,14-24"
+    pub rule: String,
+    #[serde(default = "default_radius")]
+    pub radius: usize, // 1 is the 3x3 neighbourhood, 2 the 5x5 one, ...
+    pub iterations: usize,
+    #[serde(default)]
+    pub fill_open: Option<usize>, // a tile also becomes a wall if at most this many walls are within radius + 1 (the second part of the roguebasin rule)
+}
+fn default_radius() -> usize {
+    1
+}
+
+/// rows or columns that are kept free of random walls while filling the room, so the cave is less likely to be cut in two
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct ClearCorridor {
+    pub axis: Axis, // X is a column at a random x, Y a row at a random y
+    pub width: usize,
+    pub margin: usize, // distance to the sides of the room
+}
+fn default_clear_corridor() -> Option<ClearCorridor> {
+    Some(ClearCorridor {
+        axis: Axis::X,
+        width: 1,
+        margin: 4,
+    })
+}
+impl ClearCorridor {
+    /// the columns (or rows) of the corridor, `length` is the amount of columns (or rows) of the room
+    fn pick(&self, length: usize, rng: &mut fastrand::Rng) -> std::ops::Range<usize> {
+        let range = self.margin..(length + 1).saturating_sub(self.margin + self.width);
+        let start = if !range.is_empty() {
+            rng.usize(range)
+        } else {
+            (length / 2).saturating_sub(self.width / 2)
+        };
+        start..start + self.width
+    }
+}
+
+/// the wall counts that make a tile a wall (birth) or keep it one (survival)
+#[derive(Clone, Debug, PartialEq)]
+struct BirthSurvival {
+    birth: Vec<usize>,
+    survival: Vec<usize>,
+}
+impl BirthSurvival {
+    fn parse(rule: &str) -> Option<Self> {
+        let (birth, survival) = rule.split_once('/')?;
+        let birth = birth
+            .trim()
+            .strip_prefix(|c: char| c.eq_ignore_ascii_case(&'b'))?;
+        let survival = survival
+            .trim()
+            .strip_prefix(|c: char| c.eq_ignore_ascii_case(&'s'))?;
+        Some(Self {
+            birth: Self::parse_counts(birth)?,
+            survival: Self::parse_counts(survival)?,
+        })
+    }
+
+    /// "5678" is read digit by digit, "3,5-8,12" as numbers and ranges
+    fn parse_counts(counts: &str) -> Option<Vec<usize>> {
+        if !counts.contains([',', '-']) {
+            return counts
+                .chars()
+                .map(|c| c.to_digit(10).map(|d| d as usize))
+                .collect();
+        }
+        let mut output = vec![];
+        for part in counts.split(',') {
+            match part.split_once('-') {
+                Some((from, to)) => {
+                    output.extend(from.trim().parse::<usize>().ok()?..=to.trim().parse().ok()?)
+                }
+                None => output.push(part.trim().parse().ok()?),
+            }
+        }
+        Some(output)
+    }
+}
+
+impl CellularAutomataStep {
+    fn place_wall(&self, rule: &BirthSurvival, grid: &Grid<bool>, x: usize, y: usize) -> bool {
+        let walls = count_walls_within(grid, x, y, self.radius);
+        let counts = if grid[y][x] {
+            &rule.survival
+        } else {
+            &rule.birth
+        };
+        counts.contains(&walls)
+            || self
+                .fill_open
+                .is_some_and(|max| count_walls_within(grid, x, y, self.radius + 1) <= max)
+    }
+}
+
+/// the walls within `radius` around the tile, without the tile itself. tiles outside of the grid count as walls
+fn count_walls_within(grid: &Grid<bool>, x: usize, y: usize, radius: usize) -> usize {
+    let radius = radius as i32;
+    let mut walls = 0;
+    for dy in -radius..=radius {
+        for dx in -radius..=radius {
+            if dx == 0 && dy == 0 {
+                continue;
+            }
+            let (map_x, map_y) = (x as i32 + dx, y as i32 + dy);
+            if map_x < 0
+                || map_y < 0
+                || grid
+                    .get(map_y as usize, map_x as usize)
+                    .copied()
+                    .unwrap_or(true)
+            {
+                walls += 1;
+            }
+        }
+    }
+    walls
 }
 
 /// this code stems from https://www.roguebasin.com/index.php?title=Cellular_Automata_Method_for_Generating_Random_Cave-Like_Levels
 /// and has been adapted for rust. the semantics are the same (hopefully)
 impl CellularAutomata {
-    fn generate(&self, room: &mut Room, destructive: bool) {
+    fn generate(&self, room: &mut Room, destructive: bool, rng: &mut fastrand::Rng) {
         let grid = room.get_grid();
         let mut new_grid: Grid<bool> = Grid::new(grid.rows(), grid.cols());
 
-        self.random_fill(&mut new_grid);
+        self.random_fill(&mut new_grid, rng);
 
         // should the algorithm overwrite EVERYTHING that is inside the room?
-        // yes -> every tile is random. no -> all WALL tiles are put back into the grid
-        if !destructive {
-            for y in 0..grid.rows() {
-                for x in 0..grid.cols() {
-                    match grid[y][x] {
-                        room::Tile::Wall => new_grid[y][x] = true,
-                        _ => continue,
-                    };
+        // yes -> every tile is random. no -> tiles that cannot be walked on are put back into the grid as walls,
+        // and only the floor is changed at the end
+        // the outer walls of shaped rooms are inside of the grid, they are walls from the start
+        for y in 0..grid.rows() {
+            for x in 0..grid.cols() {
+                let is_edge = room.is_edge_tile(UVec2::new(x as u32, y as u32));
+                if is_edge || (!destructive && !grid[y][x].is_walkable()) {
+                    new_grid[y][x] = true;
                 }
             }
         }
 
-        for _ in 0..self.iterations {
-            new_grid = self.step(&new_grid);
+        if self.schedule.is_empty() {
+            for _ in 0..self.iterations {
+                new_grid = self.step(&new_grid, |grid, x, y| self.place_wall_logic(grid, x, y));
+            }
+        }
+        for step in self.schedule.iter() {
+            let Some(rule) = BirthSurvival::parse(&step.rule) else {
+                warn!(
+                    "invalid cellular automata rule {}, the step is skipped",
+                    step.rule
+                );
+                continue;
+            };
+            for _ in 0..step.iterations {
+                new_grid = self.step(&new_grid, |grid, x, y| step.place_wall(&rule, grid, x, y));
+            }
         }
 
         for y in 0..new_grid.rows() {
             for x in 0..new_grid.cols() {
-                let tile = match new_grid[y][x] {
+                let position = UVec2::new(x as u32, y as u32);
+                if !destructive && !grid[y][x].is_floor() {
+                    continue;
+                }
+                // the outer walls stay walls
+                let tile = match new_grid[y][x] || room.is_edge_tile(position) {
                     true => room::Tile::Wall,
-                    false => room::Tile::Ground,
+                    false => room.floor_tile(),
                 };
-                room.set_tile(UVec2::new(x as u32, y as u32), tile);
+                room.set_tile(position, tile);
             }
         }
     }
 
-    fn random_fill(&self, grid: &mut Grid<bool>) {
-        let range = 4..grid.cols().saturating_sub(4);
-        let random_column = if !range.is_empty() {
-            fastrand::usize(range)
-        } else {
-            grid.cols() / 2
+    fn random_fill(&self, grid: &mut Grid<bool>, rng: &mut fastrand::Rng) {
+        let corridor = self.clear_corridor.as_ref().map(|corridor| {
+            let length = match corridor.axis {
+                Axis::X => grid.cols(),
+                Axis::Y => grid.rows(),
+            };
+            (&corridor.axis, corridor.pick(length, rng))
+        });
+        let is_corridor = |x: usize, y: usize| match &corridor {
+            Some((Axis::X, columns)) => columns.contains(&x),
+            Some((Axis::Y, rows)) => rows.contains(&y),
+            None => false,
         };
 
         for y in 0..grid.rows() {
             for x in 0..grid.cols() {
                 if x == 0 || y == 0 || x == grid.cols() - 1 || y == grid.rows() - 1 {
                     grid[y][x] = true;
-                } else if x != random_column && fastrand::f32() < self.wall_percentage {
+                } else if !is_corridor(x, y) && rng.f32() < self.wall_percentage {
                     grid[y][x] = true;
                 }
             }
         }
     }
 
-    fn step(&self, grid: &Grid<bool>) -> Grid<bool> {
+    fn step(
+        &self,
+        grid: &Grid<bool>,
+        place_wall: impl Fn(&Grid<bool>, usize, usize) -> bool,
+    ) -> Grid<bool> {
         let mut new_grid = Grid::new(grid.rows(), grid.cols());
 
         for y in 0..grid.rows() {
@@ -179,7 +377,7 @@ impl CellularAutomata {
                 if x == 0 || y == 0 || x == grid.cols() - 1 || y == grid.rows() - 1 {
                     new_grid[y][x] = true;
                 } else {
-                    new_grid[y][x] = self.place_wall_logic(&grid, x, y);
+                    new_grid[y][x] = place_wall(grid, x, y);
                 }
             }
         }
@@ -225,7 +423,837 @@ impl CellularAutomata {
 }
 
 impl AesthetiziseRoom for CellularAutomata {
-    fn generate_features(&self, rooms: &mut Room, destructive: bool) {
-        self.generate(rooms, destructive);
+    fn generate_features(&self, rooms: &mut Room, destructive: bool, _doors: &[UVec2], seed: u64) {
+        self.generate(rooms, destructive, &mut fastrand::Rng::with_seed(seed));
+    }
+}
+
+/// covers the floor of the room with carpet, keeping `margin` tiles free along the edges
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct Carpet {
+    pub margin: usize,
+}
+
+impl AesthetiziseRoom for Carpet {
+    fn generate_features(&self, room: &mut Room, _destructive: bool, _doors: &[UVec2], _seed: u64) {
+        for y in self.margin..room.height().saturating_sub(self.margin) {
+            for x in self.margin..room.length().saturating_sub(self.margin) {
+                let position = UVec2::new(x as u32, y as u32);
+                if room.get_tile(position).is_some_and(|tile| tile.is_floor()) {
+                    room.set_tile(position, room::Tile::Carpet);
+                }
+            }
+        }
+    }
+}
+
+/// places `amount` square patches of a tile on random floor tiles. used for rubble and hazards (pits, water, lava)
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct Scatter {
+    pub tile: room::Tile,
+    pub amount: usize,
+    pub patch_size: usize,
+}
+
+impl AesthetiziseRoom for Scatter {
+    fn generate_features(&self, room: &mut Room, destructive: bool, _doors: &[UVec2], seed: u64) {
+        if room.length() <= 2 || room.height() <= 2 {
+            return;
+        }
+        let mut rng = fastrand::Rng::with_seed(seed);
+        for _ in 0..self.amount {
+            // patches start inside of the outer walls
+            let anchor = UVec2::new(
+                rng.usize(1..room.length() - 1) as u32,
+                rng.usize(1..room.height() - 1) as u32,
+            );
+            for x in anchor.x..anchor.x + self.patch_size as u32 {
+                for y in anchor.y..anchor.y + self.patch_size as u32 {
+                    let position = UVec2::new(x, y);
+                    // the outer walls stay, without destructive only floor tiles are replaced
+                    if !room.is_edge_tile(position)
+                        && room
+                            .get_tile(position)
+                            .is_some_and(|tile| destructive || tile.is_floor())
+                    {
+                        room.set_tile(position, self.tile);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// turns noise into tiles. the noise is sampled at the global tile position, so rooms that use the same
+/// settings continue each others pattern where they touch
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct Noise {
+    pub noise_type: NoiseType,
+    pub seed: u32,
+    pub frequency: f64, // smaller values make bigger blobs
+    pub layers: Vec<NoiseLayer>,
+}
+#[derive(Clone, Copy, Debug, serde::Deserialize)]
+pub enum NoiseType {
+    Perlin,
+    Simplex,
+    Worley,
+}
+/// every tile where the noise is at least `threshold` becomes `tile`. if multiple layers match, the highest threshold wins
+#[derive(Clone, Copy, Debug, serde::Deserialize)]
+pub struct NoiseLayer {
+    pub threshold: f64, // the noise is roughly between -1 and 1
+    pub tile: room::Tile,
+}
+
+impl Noise {
+    fn sample(&self, noise: &impl NoiseFn<f64, 2>, point: IVec2) -> f64 {
+        noise.get([
+            point.x as f64 * self.frequency,
+            point.y as f64 * self.frequency,
+        ])
+    }
+
+    fn tile_for(&self, value: f64) -> Option<room::Tile> {
+        self.layers
+            .iter()
+            .filter(|layer| value >= layer.threshold)
+            .max_by(|a, b| a.threshold.total_cmp(&b.threshold))
+            .map(|layer| layer.tile)
+    }
+
+    fn generate(&self, room: &mut Room, destructive: bool, noise: &impl NoiseFn<f64, 2>) {
+        for y in 0..room.height() as u32 {
+            for x in 0..room.length() as u32 {
+                let position = UVec2::new(x, y);
+                // the outer walls stay, without destructive only floor tiles are replaced
+                if room.is_edge_tile(position)
+                    || !room
+                        .get_tile(position)
+                        .is_some_and(|tile| destructive || tile.is_floor())
+                {
+                    continue;
+                }
+                let value = self.sample(noise, room.local_to_global(position));
+                if let Some(tile) = self.tile_for(value) {
+                    room.set_tile(position, tile);
+                }
+            }
+        }
+    }
+}
+
+impl AesthetiziseRoom for Noise {
+    fn generate_features(&self, room: &mut Room, destructive: bool, _doors: &[UVec2], _seed: u64) {
+        match self.noise_type {
+            NoiseType::Perlin => self.generate(room, destructive, &Perlin::new(self.seed)),
+            NoiseType::Simplex => self.generate(room, destructive, &Simplex::new(self.seed)),
+            NoiseType::Worley => self.generate(
+                room,
+                destructive,
+                &Worley::new(self.seed).set_return_type(noise::core::worley::ReturnType::Distance),
+            ),
+        }
+    }
+}
+
+/// splits the room into smaller chambers. every split puts a wall with a doorway between the two halves,
+/// so every chamber can be reached from every other chamber (and from the doors of the room).
+/// the walls never cover the tiles inside of the doors, a chamber that would need one is not split
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct Bsp {
+    pub depth: usize,         // how often the room is split. 0 leaves the room as it is
+    pub min_cell_size: usize, // chambers are never smaller than this, in tiles
+    pub door_width: usize,    // size of the doorways between chambers
+}
+
+/// a wall between two chambers, with a doorway of `door.1` tiles starting at `door.0`
+struct BspSplit {
+    vertical: bool,   // the wall goes from bottom to top
+    position: u32,    // x of a vertical wall, y of a horizontal wall
+    span: (u32, u32), // where the wall starts and ends (exclusive) along its direction
+    door: (u32, u32),
+}
+
+impl Bsp {
+    /// splits the chamber between `min` and `max` (exclusive) and its halves until `depth` is reached.
+    /// no wall goes over one of the `kept` tiles
+    fn split(
+        &self,
+        min: UVec2,
+        max: UVec2,
+        depth: usize,
+        kept: &[UVec2],
+        splits: &mut Vec<BspSplit>,
+        rng: &mut fastrand::Rng,
+    ) {
+        if depth == 0 {
+            return;
+        }
+        // the wall takes one tile, both halves need at least min_cell_size tiles
+        let min_cell = self.min_cell_size.max(2) as u32;
+        let size = max - min;
+        let can_split = |length: u32| length >= min_cell * 2 + 1;
+        let vertical = match (can_split(size.x), can_split(size.y)) {
+            (false, false) => return,
+            (true, false) => true,
+            (false, true) => false,
+            // prefer cutting the longer side, so the chambers do not become long tubes
+            (true, true) => size.x >= size.y,
+        };
+
+        let (start, end, span) = if vertical {
+            (min.x, max.x, (min.y, max.y))
+        } else {
+            (min.y, max.y, (min.x, max.x))
+        };
+        let is_free = |position: u32| {
+            !kept.iter().any(|tile| {
+                let (across, along) = if vertical { (tile.x, tile.y) } else { (tile.y, tile.x) };
+                across == position && (span.0..span.1).contains(&along)
+            })
+        };
+        let positions = (start + min_cell..=end - 1 - min_cell)
+            .filter(|&position| is_free(position))
+            .collect::<Vec<_>>();
+        if positions.is_empty() {
+            return;
+        }
+        let position = positions[rng.usize(..positions.len())];
+        let door_width = (self.door_width.max(1) as u32).min(span.1 - span.0);
+        let door_start = rng.u32(span.0..=span.1 - door_width);
+        splits.push(BspSplit {
+            vertical,
+            position,
+            span,
+            door: (door_start, door_width),
+        });
+
+        if vertical {
+            self.split(min, UVec2::new(position, max.y), depth - 1, kept, splits, rng);
+            self.split(UVec2::new(position + 1, min.y), max, depth - 1, kept, splits, rng);
+        } else {
+            self.split(min, UVec2::new(max.x, position), depth - 1, kept, splits, rng);
+            self.split(UVec2::new(min.x, position + 1), max, depth - 1, kept, splits, rng);
+        }
+    }
+}
+
+impl AesthetiziseRoom for Bsp {
+    fn generate_features(&self, room: &mut Room, destructive: bool, doors: &[UVec2], seed: u64) {
+        if room.length() <= 2 || room.height() <= 2 {
+            return;
+        }
+        // only the inside of the room is split, the outer walls stay
+        let max = UVec2::new(room.length() as u32 - 1, room.height() as u32 - 1);
+        // the doors are on the edge, the tiles inside of them have to stay open
+        let inside_doors = doors
+            .iter()
+            .map(|door| door.clamp(UVec2::ONE, max - UVec2::ONE))
+            .collect::<Vec<_>>();
+        let mut splits = vec![];
+        self.split(
+            UVec2::ONE,
+            max,
+            self.depth,
+            &inside_doors,
+            &mut splits,
+            &mut fastrand::Rng::with_seed(seed),
+        );
+
+        let to_local = |split: &BspSplit, along: u32, across: i32| {
+            let position = split.position as i32 + across;
+            match split.vertical {
+                true => UVec2::new(position as u32, along),
+                false => UVec2::new(along, position as u32),
+            }
+        };
+        // without destructive only floor tiles become walls
+        for split in splits.iter() {
+            for along in split.span.0..split.span.1 {
+                let position = to_local(split, along, 0);
+                if room
+                    .get_tile(position)
+                    .is_some_and(|tile| destructive || tile.is_floor())
+                {
+                    room.set_tile(position, room::Tile::Wall);
+                }
+            }
+        }
+        // doorways are opened after every wall is placed. the tiles in front of a doorway are opened too,
+        // in case the wall of a smaller chamber ends right in front of it
+        let floor = room.floor_tile();
+        for split in splits.iter() {
+            for along in split.door.0..split.door.0 + split.door.1 {
+                for across in -1..=1 {
+                    let position = to_local(split, along, across);
+                    // the outer walls of shaped rooms can be inside of a split, they stay closed
+                    if !room.is_edge_tile(position)
+                        && room.get_tile(position).is_some_and(|tile| !tile.is_safe())
+                    {
+                        room.set_tile(position, floor);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// carves caves with random walkers. the inside of the room is filled with walls first.
+/// one walker goes from every door tile to the center of the room, then `walkers` walkers start at the center and the doors
+/// and carve until `floor_percentage` of the room is floor. walkers only ever start on carved tiles, so the cave is connected
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct DrunkardsWalk {
+    pub walkers: usize,
+    pub floor_percentage: f32, // 0 - 1, of the inside of the room
+    pub step_bias: f32, // chance to keep the direction of the last step. higher values make longer corridors
+    pub corridor_width: usize,
+}
+
+const DIRECTIONS: [IVec2; 4] = [IVec2::X, IVec2::Y, IVec2::NEG_X, IVec2::NEG_Y];
+
+impl DrunkardsWalk {
+    /// carves a square of corridor_width tiles around the position and returns how many new floor tiles there are
+    fn carve(&self, room: &mut Room, position: IVec2) -> usize {
+        let width = self.corridor_width.max(1) as i32;
+        let mut carved = 0;
+        for y in 0..width {
+            for x in 0..width {
+                let tile = position + IVec2::new(x, y) - IVec2::splat(width / 2);
+                if !self.is_inside(room, tile) {
+                    continue;
+                }
+                let tile = tile.as_uvec2();
+                // the outer walls of shaped rooms are never carved
+                if !room.is_edge_tile(tile) && room.get_tile(tile).is_some_and(|t| !t.is_safe()) {
+                    room.set_tile(tile, room.floor_tile());
+                    carved += 1;
+                }
+            }
+        }
+        carved
+    }
+
+    fn is_inside(&self, room: &Room, position: IVec2) -> bool {
+        position.x >= 1
+            && position.y >= 1
+            && position.x < room.length() as i32 - 1
+            && position.y < room.height() as i32 - 1
+    }
+
+    /// picks the direction of the next step. walkers turn around at the walls of the room
+    fn step(&self, room: &Room, position: IVec2, direction: &mut IVec2, rng: &mut fastrand::Rng) {
+        if rng.f32() >= self.step_bias {
+            *direction = DIRECTIONS[rng.usize(..DIRECTIONS.len())];
+        }
+        if !self.is_inside(room, position + *direction) {
+            *direction = -*direction;
+        }
+    }
+}
+
+impl AesthetiziseRoom for DrunkardsWalk {
+    fn generate_features(&self, room: &mut Room, destructive: bool, doors: &[UVec2], seed: u64) {
+        if room.length() <= 2 || room.height() <= 2 {
+            return;
+        }
+        let mut rng = fastrand::Rng::with_seed(seed);
+        // without destructive only the floor is filled, other tiles like hazards are only replaced by the walkers
+        for y in 1..room.height() as u32 - 1 {
+            for x in 1..room.length() as u32 - 1 {
+                let position = UVec2::new(x, y);
+                if destructive || room.get_tile(position).is_some_and(|t| t.is_floor()) {
+                    room.set_tile(position, room::Tile::Wall);
+                }
+            }
+        }
+
+        let center = IVec2::new(room.length() as i32 / 2, room.height() as i32 / 2);
+        let inside_area = (room.length() - 2) * (room.height() - 2);
+        let target = (inside_area as f32 * self.floor_percentage.clamp(0., 1.)) as usize;
+        let mut floor = self.carve(room, center);
+
+        // the doors are on the edge, their walkers start on the tile inside of it
+        let starts = doors
+            .iter()
+            .map(|door| {
+                door.as_ivec2().clamp(
+                    IVec2::ONE,
+                    IVec2::new(room.length() as i32 - 2, room.height() as i32 - 2),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        // a walker from each door stumbles towards the center. its trail connects the door to the cave
+        let max_steps = inside_area * 10;
+        for &start in starts.iter() {
+            let mut position = start;
+            let mut direction = DIRECTIONS[rng.usize(..DIRECTIONS.len())];
+            let mut steps = 0;
+            floor += self.carve(room, position);
+            while position != center {
+                if steps < max_steps && rng.f32() < 0.3 {
+                    self.step(room, position, &mut direction, &mut rng);
+                } else {
+                    // move along one of the axes, the longer the distance on an axis the more likely
+                    let distance = (center - position).abs();
+                    direction = if rng.i32(0..distance.x + distance.y) < distance.x {
+                        IVec2::new((center - position).x.signum(), 0)
+                    } else {
+                        IVec2::new(0, (center - position).y.signum())
+                    };
+                }
+                if self.is_inside(room, position + direction) {
+                    position += direction;
+                    floor += self.carve(room, position);
+                }
+                steps += 1;
+            }
+        }
+
+        // the other walkers carve until the room is open enough
+        let mut walkers = (0..self.walkers)
+            .map(|i| {
+                let start = match i % (starts.len() + 1) {
+                    0 => center,
+                    n => starts[n - 1],
+                };
+                (start, DIRECTIONS[rng.usize(..DIRECTIONS.len())])
+            })
+            .collect::<Vec<_>>();
+        let mut steps = 0;
+        while floor < target && steps < max_steps && !walkers.is_empty() {
+            for (position, direction) in walkers.iter_mut() {
+                self.step(room, *position, direction, &mut rng);
+                if self.is_inside(room, *position + *direction) {
+                    *position += *direction;
+                    floor += self.carve(room, *position);
+                }
+            }
+            steps += 1;
+        }
+    }
+}
+
+/// fills the inside of the room with wave function collapse (overlapping model, see wfc.rs),
+/// so the room looks like the sample up close. the outer walls are not touched, the tiles inside of the doors stay floor
+/// and without destructive every tile that is not a floor tile stays as it is.
+/// if there is a contradiction it is tried again with another seed, after `attempts` tries the room is left alone
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct Wfc {
+    pub sample: Vec<String>, // rows of tile symbols (see TILE_PROPERTIES), the first row is the top
+    pub pattern_size: usize, // 2 or 3 work best
+    pub symmetry: bool,      // use rotated and mirrored patterns of the sample too
+    pub attempts: usize,
+}
+
+impl Wfc {
+    /// the sample with the bottom row first, like the room grids
+    fn parse_sample(&self) -> Option<Vec<Vec<room::Tile>>> {
+        self.sample
+            .iter()
+            .rev()
+            .map(|row| row.chars().map(room::Tile::from_symbol).collect())
+            .collect()
+    }
+}
+
+impl AesthetiziseRoom for Wfc {
+    fn generate_features(&self, room: &mut Room, destructive: bool, doors: &[UVec2], seed: u64) {
+        if room.length() <= 2 || room.height() <= 2 {
+            return;
+        }
+        let Some(model) = self.parse_sample().and_then(|sample| {
+            OverlappingModel::from_sample(&sample, self.pattern_size, self.symmetry)
+        }) else {
+            warn!("the wfc sample is invalid, it needs rows of the same length, made of tile symbols and at least pattern_size big");
+            return;
+        };
+
+        // the model works on the inside of the room, one tile smaller on every side
+        let size = UVec2::new(room.length() as u32 - 2, room.height() as u32 - 2);
+        let mut fixed = vec![];
+        if model.contains(room::Tile::Ground) {
+            for door in doors.iter() {
+                let inside = door.as_ivec2().clamp(IVec2::ONE, size.as_ivec2()) - IVec2::ONE;
+                fixed.push((inside.as_uvec2(), room::Tile::Ground));
+            }
+        }
+        if !destructive {
+            for y in 0..size.y {
+                for x in 0..size.x {
+                    let tile = *room.get_tile(UVec2::new(x + 1, y + 1)).unwrap();
+                    if !tile.is_floor() && model.contains(tile) {
+                        fixed.push((UVec2::new(x, y), tile));
+                    }
+                }
+            }
+        }
+
+        for attempt in 0..self.attempts.max(1) as u64 {
+            let mut rng = fastrand::Rng::with_seed(
+                seed.wrapping_add(attempt.wrapping_mul(0x9e37_79b9_7f4a_7c15)),
+            );
+            let Some(output) = model.run(size.x as usize, size.y as usize, &fixed, &mut rng) else {
+                continue;
+            };
+            let floor = room.floor_tile();
+            for y in 0..size.y {
+                for x in 0..size.x {
+                    let position = UVec2::new(x + 1, y + 1);
+                    // the outer walls of shaped rooms are inside of the model too, they stay
+                    if room.is_edge_tile(position)
+                        || (!destructive && !room.get_tile(position).is_some_and(|t| t.is_floor()))
+                    {
+                        continue;
+                    }
+                    let tile = match output[y as usize][x as usize] {
+                        room::Tile::Ground => floor,
+                        tile => tile,
+                    };
+                    room.set_tile(position, tile);
+                }
+            }
+            return;
+        }
+        warn!(
+            "wfc found no solution for room {} after {} attempts",
+            room.id(),
+            self.attempts
+        );
+    }
+}
+
+/// mirrors the room onto itself: the left half onto the right half (Horizontal), the bottom half onto the top half (Vertical),
+/// both at once (FourWay), or the bottom half turned by half a turn onto the top half (Rotational).
+/// runs after the doors are carved, so whatever the other aesthetics made is mirrored.
+/// door tiles, the tiles next to them and the outer walls are kept
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct Symmetry {
+    pub mode: SymmetryMode,
+}
+#[derive(Clone, Copy, Debug, serde::Deserialize)]
+pub enum SymmetryMode {
+    Horizontal,
+    Vertical,
+    FourWay,
+    Rotational,
+}
+impl SymmetryMode {
+    /// the tile that `position` becomes a copy of. the tiles of the first half (or quarter) are their own source
+    fn source(&self, position: UVec2, size: UVec2) -> UVec2 {
+        let mirrored = size - UVec2::ONE - position;
+        match self {
+            SymmetryMode::Horizontal => UVec2::new(position.x.min(mirrored.x), position.y),
+            SymmetryMode::Vertical => UVec2::new(position.x, position.y.min(mirrored.y)),
+            SymmetryMode::FourWay => position.min(mirrored),
+            SymmetryMode::Rotational => {
+                if (position.y, position.x) <= (mirrored.y, mirrored.x) {
+                    position
+                } else {
+                    mirrored
+                }
+            }
+        }
+    }
+}
+
+impl AesthetiziseRoom for Symmetry {
+    fn generate_features(&self, room: &mut Room, _destructive: bool, _doors: &[UVec2], _seed: u64) {
+        let size = UVec2::new(room.length() as u32, room.height() as u32);
+        let is_door = |room: &Room, position: IVec2| {
+            matches!(
+                room.get_tile(position.as_uvec2()),
+                Some(room::Tile::Door | room::Tile::Ledge)
+            )
+        };
+        for y in 0..size.y {
+            for x in 0..size.x {
+                let position = UVec2::new(x, y);
+                let source = self.mode.source(position, size);
+                if source == position || room.is_edge_tile(position) {
+                    continue;
+                }
+                // keep the doors open
+                let near_door = is_door(room, position.as_ivec2())
+                    || DIRECTIONS
+                        .iter()
+                        .any(|&d| is_door(room, position.as_ivec2() + d));
+                let tile = *room.get_tile(source).unwrap();
+                if near_door
+                    || matches!(tile, room::Tile::Door | room::Tile::Ledge | room::Tile::Void)
+                {
+                    continue;
+                }
+                room.set_tile(position, tile);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::room::Tile;
+
+    #[test]
+    fn test_noise_continues_across_rooms() {
+        let noise = Noise {
+            noise_type: NoiseType::Perlin,
+            seed: 3,
+            frequency: 0.13,
+            layers: vec![
+                NoiseLayer {
+                    threshold: 0.,
+                    tile: Tile::Water,
+                },
+                NoiseLayer {
+                    threshold: 0.3,
+                    tile: Tile::Wall,
+                },
+            ],
+        };
+        // one big room and two small rooms that cover the same area
+        let mut big = Room::new(0, 20, 10, IVec2::new(0, 0), true);
+        let mut left = Room::new(1, 12, 10, IVec2::new(0, 0), true);
+        let mut right = Room::new(2, 12, 10, IVec2::new(8, 0), true);
+        for room in [&mut big, &mut left, &mut right] {
+            noise.generate_features(room, false, &[], 0);
+        }
+
+        let tile = |room: &Room, point: IVec2| {
+            *room.get_tile(room.global_to_local(point).unwrap()).unwrap()
+        };
+        // the inner tiles where the rooms overlap are the same in every room
+        for y in 1..9 {
+            for x in 9..11 {
+                let point = IVec2::new(x, y);
+                assert!(tile(&big, point) == tile(&left, point));
+                assert!(tile(&big, point) == tile(&right, point));
+            }
+        }
+        assert!(noise.tile_for(0.5) == Some(Tile::Wall));
+        assert!(noise.tile_for(-0.5).is_none());
+    }
+
+    /// flood fills from one tile, every other tile has to be found
+    fn is_connected(tiles: &std::collections::HashSet<UVec2>) -> bool {
+        let Some(&start) = tiles.iter().next() else {
+            return true;
+        };
+        let mut reached = std::collections::HashSet::from([start]);
+        let mut stack = vec![start];
+        while let Some(current) = stack.pop() {
+            for direction in [IVec2::X, IVec2::Y, IVec2::NEG_X, IVec2::NEG_Y] {
+                let next = (current.as_ivec2() + direction).as_uvec2();
+                if tiles.contains(&next) && reached.insert(next) {
+                    stack.push(next);
+                }
+            }
+        }
+        reached.len() == tiles.len()
+    }
+
+    #[test]
+    fn test_bsp_chambers_are_reachable() {
+        let bsp = Bsp {
+            depth: 4,
+            min_cell_size: 4,
+            door_width: 2,
+        };
+        // a door on the left, one on the top and one on the right
+        let doors = (18..22)
+            .map(|y| UVec2::new(0, y))
+            .chain((30..34).map(|x| UVec2::new(x, 39)))
+            .chain((5..9).map(|y| UVec2::new(59, y)))
+            .collect::<Vec<_>>();
+        for seed in 0..20 {
+            let mut room = Room::new(0, 60, 40, IVec2::new(0, 0), true);
+            room.fill_edges();
+            bsp.generate_features(&mut room, false, &doors, seed);
+
+            let inside = (1..39)
+                .flat_map(|y| (1..59).map(move |x| UVec2::new(x, y)))
+                .collect::<Vec<_>>();
+            let walkable = inside
+                .iter()
+                .copied()
+                .filter(|p| room.get_tile(*p).unwrap().is_walkable())
+                .collect::<std::collections::HashSet<_>>();
+            assert!(walkable.len() < inside.len());
+            assert!(is_connected(&walkable));
+            // no split wall ends on the tiles inside of the doors
+            for door in doors.iter() {
+                assert!(walkable.contains(&door.clamp(UVec2::ONE, UVec2::new(58, 38))));
+            }
+        }
+    }
+
+    #[test]
+    fn test_drunkards_walk() {
+        let walk = DrunkardsWalk {
+            walkers: 3,
+            floor_percentage: 0.4,
+            step_bias: 0.6,
+            corridor_width: 2,
+        };
+        let doors = [UVec2::new(0, 20), UVec2::new(30, 39), UVec2::new(49, 5)];
+        for seed in 0..20 {
+            let mut room = Room::new(0, 50, 40, IVec2::new(0, 0), true);
+            room.fill_edges();
+            walk.generate_features(&mut room, false, &doors, seed);
+
+            let walkable = (1..39)
+                .flat_map(|y| (1..49).map(move |x| UVec2::new(x, y)))
+                .filter(|p| room.get_tile(*p).unwrap().is_walkable())
+                .collect::<std::collections::HashSet<_>>();
+            assert!(walkable.len() >= (48 * 38) * 4 / 10);
+            assert!(walkable.len() < 48 * 38);
+            assert!(is_connected(&walkable));
+            // the tiles inside of the doors are open
+            for inside in [UVec2::new(1, 20), UVec2::new(30, 38), UVec2::new(48, 5)] {
+                assert!(walkable.contains(&inside));
+            }
+        }
+    }
+
+    #[test]
+    fn test_shaped_rooms_keep_their_walls() {
+        let aesthetics = [
+            Aesthetics::DrunkardsWalk(DrunkardsWalk {
+                walkers: 4,
+                floor_percentage: 0.9,
+                step_bias: 0.5,
+                corridor_width: 3,
+            }),
+            Aesthetics::Scatter(Scatter {
+                tile: Tile::Lava,
+                amount: 20,
+                patch_size: 4,
+            }),
+        ];
+        for aesthetic in aesthetics.iter() {
+            let mut details = Room::new(0, 20, 20, IVec2::ZERO, true).details;
+            details.shape = room::RoomShape::Circle;
+            let dimensions = room::RoomDimensions {
+                anchor: IVec2::ZERO,
+                length: 20,
+                height: 20,
+            };
+            let mut room = Room::new2(0, dimensions, details);
+            room.fill_edges();
+            aesthetic.generate_features(&mut room, true, &[UVec2::new(0, 10)], 0);
+            for y in 0..20 {
+                for x in 0..20 {
+                    let position = UVec2::new(x, y);
+                    if room.is_edge_tile(position) {
+                        assert!(room.get_tile(position) == Some(&Tile::Wall));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_symmetry() {
+        let walled_room = || {
+            let mut room = Room::new(0, 9, 7, IVec2::new(0, 0), true);
+            room.fill_edges();
+            room.set_tile(UVec2::new(2, 2), Tile::Wall);
+            room.set_tile(UVec2::new(0, 3), Tile::Door);
+            room
+        };
+        let symmetric = |mode| {
+            let mut room = walled_room();
+            Symmetry { mode }.generate_features(&mut room, false, &[], 0);
+            room
+        };
+        let is_wall =
+            |room: &Room, x: u32, y: u32| room.get_tile(UVec2::new(x, y)) == Some(&Tile::Wall);
+
+        let room = symmetric(SymmetryMode::Horizontal);
+        assert!(is_wall(&room, 6, 2) && !is_wall(&room, 2, 4));
+        // the door is not mirrored onto the other wall
+        assert!(room.get_tile(UVec2::new(0, 3)) == Some(&Tile::Door));
+        assert!(is_wall(&room, 8, 3));
+        let room = symmetric(SymmetryMode::Vertical);
+        assert!(is_wall(&room, 2, 4) && !is_wall(&room, 6, 2));
+        let room = symmetric(SymmetryMode::FourWay);
+        assert!(is_wall(&room, 6, 2) && is_wall(&room, 2, 4) && is_wall(&room, 6, 4));
+        let room = symmetric(SymmetryMode::Rotational);
+        assert!(is_wall(&room, 6, 4) && !is_wall(&room, 6, 2));
+
+        // the tiles next to a door stay open, even if there is a wall on the other side
+        let mut room = walled_room();
+        room.set_tile(UVec2::new(8, 4), Tile::Door);
+        room.set_tile(UVec2::new(1, 4), Tile::Wall);
+        let symmetry = Symmetry {
+            mode: SymmetryMode::Horizontal,
+        };
+        symmetry.generate_features(&mut room, true, &[], 0);
+        assert!(room.get_tile(UVec2::new(8, 4)) == Some(&Tile::Door));
+        assert!(room.get_tile(UVec2::new(7, 4)) == Some(&Tile::Ground));
+        assert!(is_wall(&room, 6, 2));
+    }
+
+    #[test]
+    fn test_cellular_automata_rules() {
+        let rule = BirthSurvival::parse("B5678/S45678").unwrap();
+        assert!(rule.birth == vec![5, 6, 7, 8] && rule.survival == vec![4, 5, 6, 7, 8]);
+        let rule = BirthSurvival::parse("b13-15,20/S").unwrap();
+        assert!(rule.birth == vec![13, 14, 15, 20] && rule.survival.is_empty());
+        assert!(BirthSurvival::parse("5678").is_none());
+        assert!(BirthSurvival::parse("B5x/S4").is_none());
+
+        // the tiles outside of the grid are walls
+        let grid: Grid<bool> = Grid::new(5, 5);
+        assert!(count_walls_within(&grid, 1, 1, 1) == 0);
+        assert!(count_walls_within(&grid, 1, 1, 2) == 9);
+
+        // nothing is born and nothing survives, only the outer walls are left
+        let automata = CellularAutomata {
+            iterations: 0,
+            wall_percentage: 1.,
+            schedule: vec![CellularAutomataStep {
+                rule: "B/S".into(),
+                radius: 1,
+                iterations: 1,
+                fill_open: None,
+            }],
+            clear_corridor: Some(ClearCorridor {
+                axis: Axis::X,
+                width: 2,
+                margin: 3,
+            }),
+        };
+        let mut grid: Grid<bool> = Grid::new(10, 20);
+        automata.random_fill(&mut grid, &mut fastrand::Rng::with_seed(0));
+        // the corridor is the only part without walls
+        let free_columns = (1..19)
+            .filter(|&x| (1..9).all(|y| !grid[y][x]))
+            .collect::<Vec<_>>();
+        assert!(free_columns.len() == 2 && free_columns[0] + 1 == free_columns[1]);
+        assert!(free_columns[0] >= 3 && free_columns[1] <= 16);
+
+        let mut room = Room::new(0, 20, 10, IVec2::new(0, 0), true);
+        automata.generate_features(&mut room, true, &[], 0);
+        for y in 0..10 {
+            for x in 0..20 {
+                let position = UVec2::new(x, y);
+                let is_wall = room.get_tile(position) == Some(&Tile::Wall);
+                assert!(is_wall == room.is_edge_tile(position));
+            }
+        }
+
+        // without destructive every tile that is not floor stays
+        let mut room = Room::new(0, 20, 10, IVec2::new(0, 0), true);
+        room.set_tile(UVec2::new(5, 5), Tile::Lava);
+        room.set_tile(UVec2::new(0, 4), Tile::Door);
+        room.set_tile(UVec2::new(8, 3), Tile::Wall);
+        automata.generate_features(&mut room, false, &[], 0);
+        assert!(room.get_tile(UVec2::new(5, 5)) == Some(&Tile::Lava));
+        assert!(room.get_tile(UVec2::new(0, 4)) == Some(&Tile::Door));
+        assert!(room.get_tile(UVec2::new(8, 3)) == Some(&Tile::Wall));
+        assert!(room.get_tile(UVec2::new(8, 4)) == Some(&Tile::Ground));
     }
 }
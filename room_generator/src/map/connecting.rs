@@ -1,10 +1,17 @@
 use bevy::prelude::*;
 use bresenham::Bresenham;
 use itertools::Itertools;
+use noise::{NoiseFn, Perlin};
+
+use ordered_float::OrderedFloat;
 
 use super::{
-    generation::{MapArea, MapResource, WorldgenSettings},
-    room::{is_overlapping, DoorOrientation, Room, RoomDimensions, Structure, StructureDimensions},
+    generation::{MapArea, MapResource, WorldgenError, WorldgenSettings},
+    graphing::MyGraph,
+    room::{
+        distance_between_structures, is_overlapping, DoorOrientation, Room, RoomDimensions,
+        HallwayDetails, RoomType, Structure, StructureDimensions,
+    },
     util::{find_out_door_orientation, maybe_flip_bl_tr, IsizeTupleConverter},
 };
 use crate::map::util::*;
@@ -26,19 +33,44 @@ pub enum RoomConnectionType {
     Unknown,                                    // default or something else. should never happen
 }
 
+impl RoomConnectionType {
+    /// name of the variant, without the data it contains
+    pub fn variant_name(&self) -> &'static str {
+        match self {
+            RoomConnectionType::Adjacent(_) => "Adjacent",
+            RoomConnectionType::Separated => "Separated",
+            RoomConnectionType::SeparatedRoomsInbetween(_) => "SeparatedRoomsInbetween",
+            RoomConnectionType::SeparatedNoSolution => "SeparatedNoSolution",
+            RoomConnectionType::Unknown => "Unknown",
+        }
+    }
+}
+
+/// gameplay attributes of a connection. they always describe the door through which room2 is entered
+#[derive(Debug, PartialEq, Eq, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[rustfmt::skip]
+pub enum ConnectionAttribute {
+    OneWay,                 // a drop. can only be passed from room1 to room2
+    Secret,                 // the door is not carved and looks like a wall
+    Locked(Option<usize>),  // contains the id of the key that opens the door. None -> the key still has to be placed
+    BossGate,               // opens after the boss has been defeated
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct RoomConnection {
     pub room1_id: usize,
     pub room2_id: usize,
     pub data: RoomConnectionType,
+    pub attributes: Vec<ConnectionAttribute>,
 }
 
 impl RoomConnection {
-    fn new(room1_id: usize, room2_id: usize, ctype: RoomConnectionType) -> Self {
+    pub fn new(room1_id: usize, room2_id: usize, ctype: RoomConnectionType) -> Self {
         Self {
             room1_id,
             room2_id,
             data: ctype,
+            attributes: Vec::new(),
         }
     }
     fn is_adjacent(&self) -> bool {
@@ -47,6 +79,21 @@ impl RoomConnection {
             _ => false,
         }
     }
+    pub fn has_attribute(&self, attribute: ConnectionAttribute) -> bool {
+        self.attributes.contains(&attribute)
+    }
+    pub fn is_locked(&self) -> bool {
+        self.attributes
+            .iter()
+            .any(|a| matches!(a, ConnectionAttribute::Locked(_)))
+    }
+    /// id of the key that opens this connection, if it is locked and a key has been placed
+    pub fn lock(&self) -> Option<usize> {
+        self.attributes.iter().find_map(|a| match a {
+            ConnectionAttribute::Locked(key) => *key,
+            _ => None,
+        })
+    }
 }
 
 enum LHallwayOrientation {
@@ -57,39 +104,267 @@ enum LHallwayOrientation {
 }
 
 // TODO: change name
-pub fn connect_rooms(worldgen: Res<WorldgenSettings>, mut map: ResMut<MapResource>) {
+pub fn connect_rooms(
+    map: &mut MapResource,
+    worldgen: &WorldgenSettings,
+) -> Result<(), WorldgenError> {
     let mut connections = vec![];
-
-    // first we prepare connections for all the main rooms from the graph
-    for (room1_id, room2_id, _) in map
+    let room_graph = map
         .map_area()
         .graph
-        .as_ref()
-        .expect("cannot connect rooms without a graph")
-        .reassembled_graph
-        .all_edges()
-    {
+        .clone()
+        .ok_or_else(|| WorldgenError::MissingStageOutput("graph".into()))?;
+
+    // first we prepare connections for all the main rooms from the graph
+    for (room1_id, room2_id, _) in room_graph.reassembled_graph.all_edges() {
+        // the graph is undirected, but attributes from presets are not. use the direction of the preset if there is one
+        let (room1_id, room2_id, attributes) =
+            match map.map_area().connection_attributes.get(&(room1_id, room2_id)) {
+                Some(attributes) => (room1_id, room2_id, attributes.clone()),
+                None => match map.map_area().connection_attributes.get(&(room2_id, room1_id)) {
+                    Some(attributes) => (room2_id, room1_id, attributes.clone()),
+                    None => (room1_id, room2_id, Vec::new()),
+                },
+            };
         let room1 = &map.map_area().rooms[&room1_id];
         let room2 = &map.map_area().rooms[&room2_id];
 
-        let ctype = find_out_connection_type(room1, room2, map.map_area(), &worldgen);
-        connections.push(RoomConnection::new(room1_id, room2_id, ctype));
+        let ctype = find_out_connection_type(room1, room2, map.map_area(), worldgen);
+        let mut connection = RoomConnection::new(room1_id, room2_id, ctype);
+        connection.attributes = attributes;
+        connections.push(connection);
+    }
+
+    let mut connections = reduce_until_adjacent(connections, map, worldgen);
+
+    // some connections might not have been buildable. use other edges of the delaunay graph to keep the level connected
+    reconnect_components(&mut connections, map, worldgen);
+
+    // the level is useless if the end cannot be reached from the start
+    if let (Some(&start), Some(&end)) = (
+        room_graph.main_path_rooms.first(),
+        room_graph.main_path_rooms.last(),
+    ) {
+        let mut adjacency = MyGraph::default();
+        adjacency.add_node(start);
+        adjacency.add_node(end);
+        for c in connections.iter().filter(|c| c.is_adjacent()) {
+            adjacency.add_edge(c.room1_id, c.room2_id, 0.);
+        }
+        if !petgraph::algo::has_path_connecting(&adjacency.0, start, end, None) {
+            return Err(WorldgenError::DisconnectedGraph);
+        }
     }
 
-    // while not all connections are adjacent, run the algorithm
+    // save all connections into the map area
+    map.map_area_mut().connections = Some(connections);
+    Ok(())
+}
+
+/// hallways of different connections can end up on top of or right next to each other.
+/// after `outer_walls` those look like parallel tubes with a double wall between them.
+/// this merges them into one bigger junction room and rewrites the connections to use that room
+pub fn merge_hallways(
+    map: &mut MapResource,
+    worldgen: &WorldgenSettings,
+) -> Result<(), WorldgenError> {
+    let mut connections = map
+        .map_area()
+        .connections
+        .clone()
+        .ok_or_else(|| WorldgenError::MissingStageOutput("connections".into()))?;
+
+    while let Some((keep, remove)) = find_hallways_to_merge(map.map_area(), &connections) {
+        info!("merging hallway {remove} into hallway {keep}");
+        merge_two_rooms(keep, remove, map, &mut connections, worldgen);
+    }
+
+    map.map_area_mut().connections = Some(connections);
+    Ok(())
+}
+
+/// two hallways can be merged if they overlap or touch, and the rectangle around both of them
+/// is not bigger than the two hallways together (so no corner is filled with empty space) and does not overlap any other room.
+/// segments that are connected to each other are supposed to touch, those are never merged
+fn find_hallways_to_merge(
+    map_area: &MapArea,
+    connections: &[RoomConnection],
+) -> Option<(usize, usize)> {
+    let connected = connections
+        .iter()
+        .map(|c| edge_key(c.room1_id, c.room2_id))
+        .collect::<bevy::utils::HashSet<_>>();
+
+    for (&a, &b) in map_area.hallways().iter().tuple_combinations() {
+        if connected.contains(&edge_key(a.id(), b.id())) {
+            continue;
+        }
+        let (overlap_x, overlap_y) = common_edge(a, b);
+        let touching = overlap_x >= 0 && overlap_y >= 0 && overlap_x.max(overlap_y) > 0;
+        if !is_overlapping(a, b) && !touching {
+            continue;
+        }
+        let merged = bounding_box(a, b);
+        if merged.length * merged.height > a.length() * a.height() + b.length() * b.height() {
+            continue;
+        }
+        let blocked = map_area
+            .rooms
+            .values()
+            .any(|r| r.id() != a.id() && r.id() != b.id() && is_overlapping(&merged, r));
+        if !blocked {
+            return Some((a.id(), b.id()));
+        }
+    }
+    None
+}
+
+/// replaces room `keep` with the rectangle around `keep` and `remove`, and deletes `remove`.
+/// every connection of `remove` is moved over to `keep`, and so are the hallway segments of `remove`
+fn merge_two_rooms(
+    keep: usize,
+    remove: usize,
+    map: &mut MapResource,
+    connections: &mut Vec<RoomConnection>,
+    worldgen: &WorldgenSettings,
+) {
+    let map_area = map.map_area_mut();
+    let Some(removed_room) = map_area.remove_room(remove) else {
+        return;
+    };
+    let kept_room = &map_area.rooms[&keep];
+    let mut details = kept_room.details.clone();
+    if let (RoomType::Hallway(kept), Some(removed)) =
+        (&mut details.room_type, removed_room.hallway_details())
+    {
+        kept.segments.extend(removed.segments.iter().copied());
+    }
+    let merged = Room::new2(keep, bounding_box(kept_room, &removed_room), details);
+    map_area.insert_room(merged);
+
+    for c in connections.iter_mut() {
+        if c.room1_id == remove {
+            c.room1_id = keep;
+        }
+        if c.room2_id == remove {
+            c.room2_id = keep;
+        }
+    }
+    connections.retain(|c| c.room1_id != c.room2_id);
+
+    // the same two rooms might be connected twice now. keep the first connection, but not lose any attributes
+    let mut merged_connections: Vec<RoomConnection> = vec![];
+    for c in connections.drain(..) {
+        match merged_connections
+            .iter_mut()
+            .find(|m| edge_key(m.room1_id, m.room2_id) == edge_key(c.room1_id, c.room2_id))
+        {
+            Some(existing) => {
+                for attribute in c.attributes {
+                    if !existing.attributes.contains(&attribute) {
+                        existing.attributes.push(attribute);
+                    }
+                }
+            }
+            None => merged_connections.push(c),
+        }
+    }
+
+    // the merged room has a new size, so the tiles that make up the doors need to be found again
+    refresh_connections(&[keep], map.map_area(), &mut merged_connections, worldgen);
+    *connections = merged_connections;
+}
+
+/// finds out the connection type again for every connection of the given rooms.
+/// needed after rooms changed their size or position, because the adjacent tiles are stored in local coordinates
+pub fn refresh_connections(
+    room_ids: &[usize],
+    map_area: &MapArea,
+    connections: &mut [RoomConnection],
+    worldgen: &WorldgenSettings,
+) {
+    for c in connections
+        .iter_mut()
+        .filter(|c| room_ids.contains(&c.room1_id) || room_ids.contains(&c.room2_id))
+    {
+        let room1 = &map_area.rooms[&c.room1_id];
+        let room2 = &map_area.rooms[&c.room2_id];
+        c.data = find_out_connection_type(room1, room2, map_area, worldgen);
+    }
+}
+
+/// the smallest rectangle that contains both structures
+fn bounding_box(a: &impl StructureDimensions, b: &impl StructureDimensions) -> RoomDimensions {
+    let anchor = a.anchor_grid().min(b.anchor_grid());
+    let end = a.anchor_grid_end().max(b.anchor_grid_end());
+    RoomDimensions {
+        anchor,
+        length: (end.x - anchor.x) as usize,
+        height: (end.y - anchor.y) as usize,
+    }
+}
+
+/// while not all connections are adjacent, run the algorithm
+fn reduce_until_adjacent(
+    mut connections: Vec<RoomConnection>,
+    map: &mut MapResource,
+    worldgen: &WorldgenSettings,
+) -> Vec<RoomConnection> {
     let mut iteration_count = 0;
     while !connections.iter().all(|c| c.is_adjacent()) {
-        connections = reduce_connections(connections, &mut map, &worldgen);
+        connections = reduce_connections(connections, map, worldgen);
         // failsafe
-        // this can lead to unconnected graphs... for now
+        // this can lead to unconnected graphs, which are fixed by reconnect_components
         iteration_count += 1;
         if iteration_count >= 10 {
             break;
         }
     }
+    connections
+}
 
-    // save all connections into the map area
-    map.map_area_mut().connections = Some(connections);
+/// looks for main rooms that cannot reach each other through the adjacent connections.
+/// for those, the shortest edge of the delaunay graph between the two groups is turned into a new connection.
+/// this is repeated until everything is connected or no edge is left to try
+fn reconnect_components(
+    connections: &mut Vec<RoomConnection>,
+    map: &mut MapResource,
+    worldgen: &WorldgenSettings,
+) {
+    let Some(delaunay) = map.map_area().graph.as_ref().map(|g| g.delaunay.clone()) else {
+        return;
+    };
+    let mut candidates = delaunay
+        .all_edges()
+        .map(|(a, b, weight)| (a, b, OrderedFloat(*weight)))
+        .sorted_by_key(|(a, b, weight)| (*weight, *a, *b))
+        .collect_vec();
+
+    loop {
+        let mut adjacency = MyGraph::default();
+        for c in connections.iter().filter(|c| c.is_adjacent()) {
+            adjacency.add_edge(c.room1_id, c.room2_id, 0.);
+        }
+        let position = candidates.iter().position(|(a, b, _)| {
+            !petgraph::algo::has_path_connecting(&adjacency.0, *a, *b, None)
+        });
+        let Some(position) = position else {
+            return;
+        };
+        let (room1_id, room2_id, _) = candidates.remove(position);
+
+        let room1 = &map.map_area().rooms[&room1_id];
+        let room2 = &map.map_area().rooms[&room2_id];
+        let ctype = find_out_connection_type(room1, room2, map.map_area(), worldgen);
+        info!("reconnecting rooms {room1_id} and {room2_id} with another graph edge");
+
+        let mut new_connections = reduce_until_adjacent(
+            vec![RoomConnection::new(room1_id, room2_id, ctype)],
+            map,
+            worldgen,
+        );
+        connections.append(&mut new_connections);
+    }
 }
 
 /// reduces the connections list to only contain connections of type Adjacent
@@ -124,9 +399,19 @@ fn reduce_connections(
                     worldgen,
                 );
             }
-            /* RoomConnectionType::SeparatedNoSolution => {
-                //new_connections.push(connection); // CHANGE THIS
-            } */
+            RoomConnectionType::SeparatedNoSolution => {
+                // try to take a detour through another room. if that is not possible either, the connection is dropped
+                match find_detour_room(&connection, map.map_area(), worldgen) {
+                    Some(detour_room) => new_connections.push(RoomConnection {
+                        data: RoomConnectionType::SeparatedRoomsInbetween([detour_room].into()),
+                        ..connection
+                    }),
+                    None => warn!(
+                        "rooms {} and {} cannot be connected",
+                        connection.room1_id, connection.room2_id
+                    ),
+                }
+            }
             _ => {
                 warn!("unknown RoomConnectionType while reducing connections");
             }
@@ -154,7 +439,7 @@ fn find_out_connection_type(
     }
 
     // could a hallway be made between those two rooms? yes -> Separated
-    if let Some(_) = can_rooms_be_connected(room1, room2, worldgen.min_passage_width) {
+    if let Some(_) = can_rooms_be_connected(room1, room2, map_area, worldgen) {
         return RoomConnectionType::Separated;
     }
 
@@ -178,6 +463,7 @@ fn connect_multiple_hallways(
         .into_iter()
         .chain(inbetween_rooms)
         .chain([connection.room2_id]);
+    let mut chain = vec![];
     // for each pair of rooms (R1->R2; R2->R3; etc)
     for (room1_id, room2_id) in iterator.tuple_windows() {
         let room1 = &map.map_area().rooms[&room1_id];
@@ -187,7 +473,17 @@ fn connect_multiple_hallways(
 
         // we cannot create new hallways here, because it's not guaranteed that the resulting RoomConnectionType == Separated
         // so we push the new connection back onto new_connections. they will be handled by the next iteration
-        new_connections.push(RoomConnection::new(room1_id, room2_id, ctype));
+        chain.push(RoomConnection::new(room1_id, room2_id, ctype));
+    }
+    pass_on_attributes(connection, &mut chain);
+    new_connections.append(&mut chain);
+}
+
+/// a connection that got split up into multiple connections hands its attributes to the last one of them,
+/// which is the one that leads into room2
+fn pass_on_attributes(connection: &RoomConnection, chain: &mut [RoomConnection]) {
+    if let Some(last) = chain.last_mut() {
+        last.attributes = connection.attributes.clone();
     }
 }
 
@@ -216,15 +512,23 @@ fn create_and_connect_hallways(
     // if create_hallway_dimensions returned valid hallways
     if let Ok(room_dimensions) = hallways {
         let mut new_room_ids = vec![];
+        // the width of a segment is the length of the edge it shares with the previous segment (or room1)
+        let mut previous_segment = RoomDimensions::from_dyn_structure_dim(room1);
 
         // create an actual room from just the dimensions and push it onto the list of all rooms
-        for &hallway in room_dimensions.into_iter() {
-            let created_room = turn_dimensions_into_room(&hallway, map);
+        for (segment_index, &hallway) in room_dimensions.iter().enumerate() {
+            let (overlap_x, overlap_y) = common_edge(&previous_segment, &hallway);
+            let details = HallwayDetails::new(
+                (connection.room1_id, connection.room2_id),
+                segment_index,
+                overlap_x.max(overlap_y).max(0) as u32,
+            );
+            previous_segment = hallway;
+
+            let created_room = turn_dimensions_into_room(&hallway, details, map);
             // we also need a ids vector to create connections between old and new rooms
             new_room_ids.push(created_room.id());
-            map.map_area_mut()
-                .rooms
-                .insert(created_room.id(), created_room);
+            map.map_area_mut().insert_room(created_room);
         }
 
         // because we just connected two rooms (R1, R2) with a hallway (R3), there are new adjacent connections
@@ -237,6 +541,7 @@ fn create_and_connect_hallways(
             .into_iter()
             .chain(new_room_ids)
             .chain([connection.room2_id]);
+        let mut chain = vec![];
         for (room1_id, room2_id) in iterator.into_iter().tuple_windows() {
             // fetch the two rooms that need to be connected
             let room1 = &map.map_area().rooms[&room1_id];
@@ -246,18 +551,26 @@ fn create_and_connect_hallways(
             // the hallway should always be placed next to a room. so it MUST be adjacent to room1 and room2
             //assert!(matches!(ctype, RoomConnectionType::Adjacent(_)));
 
-            new_connections.push(RoomConnection::new(room1_id, room2_id, ctype));
+            chain.push(RoomConnection::new(room1_id, room2_id, ctype));
         }
+        pass_on_attributes(connection, &mut chain);
+        new_connections.append(&mut chain);
         return Ok(());
     } else {
         match hallways.unwrap_err() {
-            CreateHallwayError::Unknown => return Ok(()),
-            CreateHallwayError::OverlapNotEnough => return Ok(()),
+            // let the next iteration look for a detour
+            CreateHallwayError::Unknown | CreateHallwayError::OverlapNotEnough => {
+                return Err(RoomConnection {
+                    data: RoomConnectionType::SeparatedNoSolution,
+                    ..connection.clone()
+                })
+            }
             CreateHallwayError::CouldNotMakeLShapedHallway(ids) => {
                 return Err(RoomConnection {
                     room1_id: ids.0,
                     room2_id: ids.2,
                     data: RoomConnectionType::SeparatedRoomsInbetween([ids.1].into()),
+                    attributes: connection.attributes.clone(),
                 })
             }
         }
@@ -266,7 +579,10 @@ fn create_and_connect_hallways(
 
 // source: self-modified version of https://stackoverflow.com/questions/306316/determine-if-two-rectangles-overlap-each-other
 /// returns either Some(overlap, orientation) or None if no overlap is present (rooms are not adjacent)
-fn are_two_rooms_adjacent(
+///
+/// shaped rooms are only adjacent where their footprint reaches the side of the bounding box (see `footprint_edge`),
+/// the overlap is the length of that part
+pub fn are_two_rooms_adjacent(
     room1: &impl StructureDimensions,
     room2: &impl StructureDimensions,
     min_hallway_width: u32,
@@ -279,13 +595,30 @@ fn are_two_rooms_adjacent(
         if overlap_x < 0 || overlap_y < 0 {
             return None;
         }
-        let overlap = overlap_x.max(overlap_y);
         let orientation = find_out_door_orientation(overlap_x, overlap_y);
-        return Some((overlap, orientation));
+        let (start, end) = footprint_edge(room1, room2, orientation);
+        // rooms that only touch at a corner or with their Void tiles are not adjacent
+        if end - start < min_hallway_width.max(1) as i32 {
+            return None;
+        }
+        return Some((end - start, orientation));
     }
     return None;
 }
 
+/// a hallway that ends at a room has to end at the footprint of the room, not at the Void corner of its bounding box.
+/// hallways that do not touch the room are fine
+fn attaches_to_footprint(
+    hallway: &RoomDimensions,
+    room: &impl StructureDimensions,
+    min_hallway_width: u32,
+) -> bool {
+    let (overlap_x, overlap_y) = common_edge(hallway, room);
+    let touches = (overlap_x == 0 && overlap_y >= min_hallway_width as i32)
+        || (overlap_y == 0 && overlap_x >= min_hallway_width as i32);
+    !touches || are_two_rooms_adjacent(hallway, room, min_hallway_width).is_some()
+}
+
 /// Returns None if there are no rooms present between room1 and room2.
 ///
 /// Returns Some(Vec<usize>) with Vec<usize> = room IDs of the rooms that are between room1 and room2
@@ -304,8 +637,8 @@ fn rooms_between(
     let mut inbetween_room_ids: Vec<usize> = vec![];
     for &point in list_of_points.iter() {
         let point = (point.0 as i32, point.1 as i32);
-        // if this position contains a room.........
-        if let Some(found_room_id) = map.point_to_room(point) {
+        // every room at this position, rooms can still overlap here
+        for found_room_id in map.rooms_at(point) {
             // we dont want to add the two "to be connected" rooms to the list, and we dont wnat any duplicate ids either
             if found_room_id == room1.id()
                 || found_room_id == room2.id()
@@ -329,21 +662,54 @@ fn rooms_between(
 /// it does this by first constructing a hallway between 1 and 2, and then checking that hallway for collisions with other rooms
 /// iteratively it tries to reduce the room width until the minimum width is reached.
 /// if a hallway still cannot be made, None is returned.
-/// if a hallway can be made, at any point (earlier -> wider), Some(RoomDimensions) is returned
+/// if a hallway can be made, at any point (earlier -> wider), Some(hallway dimensions) is returned
 fn can_rooms_be_connected(
     room1: &impl Structure,
     room2: &impl Structure,
-    min_hallway_width: u32,
-) -> Option<RoomDimensions> {
-    /* let hallways = create_hallway_dimensions(room1, room2, min_hallway_width);
-    if let Ok(hallways) = hallways {
+    map: &MapArea,
+    worldgen: &WorldgenSettings,
+) -> Option<Box<[RoomDimensions]>> {
+    let max_hallway_width = worldgen.max_passage_width.max(worldgen.min_passage_width);
+    create_hallway_dimensions(
+        room1,
+        room2,
+        worldgen.min_passage_width,
+        max_hallway_width,
+        map,
+        worldgen,
+    )
+    .ok()
+}
 
-    } */
-    Some(RoomDimensions {
-        anchor: IVec2::default(),
-        height: 0,
-        length: 0,
-    })
+/// looks for a room that both rooms of the connection can be connected to.
+/// rooms that are closest to both rooms are tried first
+fn find_detour_room(
+    connection: &RoomConnection,
+    map: &MapArea,
+    worldgen: &WorldgenSettings,
+) -> Option<usize> {
+    let room1 = &map.rooms[&connection.room1_id];
+    let room2 = &map.rooms[&connection.room2_id];
+
+    map.rooms
+        .values()
+        .filter(|r| r.id() != room1.id() && r.id() != room2.id())
+        .sorted_by_key(|r| {
+            (
+                OrderedFloat(
+                    distance_between_structures(room1, *r) + distance_between_structures(*r, room2),
+                ),
+                r.id(),
+            )
+        })
+        .take(5)
+        .find(|&r| {
+            let to_detour = find_out_connection_type(room1, r, map, worldgen);
+            let from_detour = find_out_connection_type(r, room2, map, worldgen);
+            to_detour != RoomConnectionType::SeparatedNoSolution
+                && from_detour != RoomConnectionType::SeparatedNoSolution
+        })
+        .map(|r| r.id())
 }
 
 #[derive(Debug, Default)]
@@ -364,101 +730,104 @@ fn create_hallway_dimensions(
     worldgen: &WorldgenSettings,
 ) -> Result<Box<[RoomDimensions]>, CreateHallwayError> {
     let (overlap_x, overlap_y) = common_edge(room1, room2);
-    let overlap = overlap_x.max(overlap_y);
     let orientation = find_out_door_orientation(overlap_x, overlap_y);
+    // a straight hallway only covers the part of the common edge where both footprints reach the side of their room
+    let (span_start, span_end) = footprint_edge(room1, room2, orientation);
+    let overlap = span_end - span_start;
 
-    let mut new_hallways: Vec<RoomDimensions>;
+    let new_hallways: Vec<RoomDimensions>;
+    // the first room that was in the way of a hallway. used to route the hallway through that room instead
+    let mut first_overlapping_room = None;
 
     // case: straight hallway
     // can a straight hallway between the two rooms be made? yes -> straight hallway
-    if overlap >= min_hallway_width as i32 {
-        /* println!(
-            "straight hallway from room1 {} to room2 {} has overlap {}",
-            room1.id(),
-            room2.id(),
-            overlap
-        ); */
-        new_hallways = [generate_straight_hallway(
+    if overlap >= min_hallway_width.max(1) as i32 {
+        let mut hallway = generate_straight_hallway(
             room1,
             room2,
             overlap as u32,
             max_hallway_width,
             &orientation,
             worldgen.threshold,
-        )]
-        .into();
-        return Ok(new_hallways.into());
-    }
-
-    // case: L shaped hallway
-    // the overlap between the two rooms is not enough to create a straight line between them, we need to make an L shaped hallway
-    // check if room1 is the left room, if not, room2 is assigned left room. -> algo needs to do less cases
-    let (flipped, left_room, right_room) =
-        maybe_flip_bl_tr(room1.anchor_grid().x < room2.anchor_grid().x, room1, room2);
-
-    // if the left room is lower than the right room on the y axis
-    //   |-->  [right]
-    //   |       |^
-    // [left] ---|
-    // then its reachable by going right->up or up-right
-    // otherwise we have this situation
-    // [left] <----
-    //  |^         |
-    //  |----- [right]
-    // and we need to go left and up to reach the other room
-    let mut orientations = if left_room.anchor_grid().y < right_room.anchor_grid().y {
-        vec![LHallwayOrientation::RightUp, LHallwayOrientation::UpRight]
-    } else {
-        vec![LHallwayOrientation::LeftUp, LHallwayOrientation::UpLeft]
-    };
-
-    loop {
-        let orientation = orientations.pop();
-        if orientation.is_none() {
-            return Err(CreateHallwayError::OverlapNotEnough);
-        }
-        new_hallways = generate_l_shaped_hallway(
-            left_room,
-            right_room,
-            min_hallway_width,
-            max_hallway_width,
-            &orientation.unwrap(),
         );
+        match orientation {
+            DoorOrientation::Vertical => hallway.anchor.y = span_start,
+            DoorOrientation::Horziontal => hallway.anchor.x = span_start,
+        }
+        new_hallways = [hallway].into();
+        let overlapping_rooms = overlapping_rooms(&new_hallways, room1, room2, map);
+        if overlapping_rooms.is_empty() {
+            return Ok(new_hallways.into());
+        }
+        first_overlapping_room = overlapping_rooms.first().copied();
+    }
 
-        // if the order of initial rooms were flipped, we need to flip the order of new hallways, or else they will be connected in a wrong way later on
-        if flipped {
-            new_hallways.reverse()
-        }
-
-        let mut overlapping_rooms = vec![];
-        for hallway in &new_hallways {
-            for room in map.rooms.values() {
-                if is_overlapping(hallway, room) {
-                    //println!("overlapping room: {}. between {} and {}", room.id(), room1.id(), room2.id() );
-                    if room.id() != room1.id()
-                        && room.id() != room2.id()
-                        && !overlapping_rooms.contains(&room.id())
-                    {
-                        overlapping_rooms.push(room.id());
-                    }
+    // case: bent hallways (L, Z, stepped, winding)
+    // the overlap between the two rooms is not enough to create a straight line between them, we need to make a hallway with bends
+    // the styles are tried in a random order, weighted by the settings. the order only depends on the two rooms,
+    // so checking if a hallway can be made and actually making it later gives the same result
+    let mut rng = hallway_rng(room1.id(), room2.id(), worldgen);
+    for style in hallway_style_order(&mut rng, worldgen) {
+        // try the widest hallway first, and make it thinner if it collides with other rooms
+        for hallway_width in (min_hallway_width..=max_hallway_width).rev() {
+            let candidates = generate_bent_hallways(
+                room1,
+                room2,
+                min_hallway_width,
+                hallway_width,
+                style,
+                worldgen,
+                &mut rng,
+            );
+            for new_hallways in candidates {
+                let attaches = new_hallways.iter().all(|hallway| {
+                    attaches_to_footprint(hallway, room1, min_hallway_width)
+                        && attaches_to_footprint(hallway, room2, min_hallway_width)
+                });
+                if !attaches {
+                    continue;
+                }
+                let overlapping_rooms = overlapping_rooms(&new_hallways, room1, room2, map);
+                if overlapping_rooms.is_empty() {
+                    return Ok(new_hallways.into());
+                }
+                if first_overlapping_room.is_none() {
+                    first_overlapping_room = overlapping_rooms.first().copied();
                 }
             }
         }
-        if !overlapping_rooms.is_empty() {
-            /* println!(
-                "overlapping rooms: {:?}. between {} and {}",
-                overlapping_rooms,
-                room1.id(),
-                room2.id()
-            ); */
-            return Err(CreateHallwayError::CouldNotMakeLShapedHallway((
-                room1.id(),
-                overlapping_rooms.first().unwrap().clone(),
-                room2.id(),
-            )));
+    }
+
+    match first_overlapping_room {
+        Some(overlapping_room) => Err(CreateHallwayError::CouldNotMakeLShapedHallway((
+            room1.id(),
+            overlapping_room,
+            room2.id(),
+        ))),
+        None => Err(CreateHallwayError::OverlapNotEnough),
+    }
+}
+
+/// ids of all rooms (except room1 and room2) that overlap with any of the hallways
+fn overlapping_rooms(
+    hallways: &[RoomDimensions],
+    room1: &impl Structure,
+    room2: &impl Structure,
+    map: &MapArea,
+) -> Vec<usize> {
+    let mut overlapping_rooms = vec![];
+    for hallway in hallways {
+        for room in map.rooms.values().sorted_by_key(|r| r.id()) {
+            if is_overlapping(hallway, room)
+                && room.id() != room1.id()
+                && room.id() != room2.id()
+                && !overlapping_rooms.contains(&room.id())
+            {
+                overlapping_rooms.push(room.id());
+            }
         }
-        return Ok(new_hallways.into());
     }
+    overlapping_rooms
 }
 
 /// creates a dimensions of a new room that can connect two other rooms.
@@ -593,14 +962,305 @@ fn generate_l_shaped_hallway(
     new_rooms
 }
 
-fn turn_dimensions_into_room(input: &impl StructureDimensions, map: &mut MapResource) -> Room {
-    Room::new(
+/// every hallway that is not straight. the weights for each style are found in WorldgenSettings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HallwayStyle {
+    LShaped,  // one bend
+    ZShaped,  // two bends
+    Stepped,  // stairs that follow the diagonal between the rooms
+    Winding,  // like stepped, but the size of the steps follows perlin noise
+}
+
+/// rng that only depends on the global seed and the two rooms
+fn hallway_rng(room1_id: usize, room2_id: usize, worldgen: &WorldgenSettings) -> fastrand::Rng {
+    fastrand::Rng::with_seed(worldgen.global_seed ^ ((room1_id as u64) << 32) ^ room2_id as u64)
+}
+
+/// all hallway styles with a weight above 0, in a random order. styles with a higher weight are more likely to be first
+fn hallway_style_order(rng: &mut fastrand::Rng, worldgen: &WorldgenSettings) -> Vec<HallwayStyle> {
+    let mut styles = vec![
+        (HallwayStyle::LShaped, worldgen.l_hallway_weight),
+        (HallwayStyle::ZShaped, worldgen.z_hallway_weight),
+        (HallwayStyle::Stepped, worldgen.stepped_hallway_weight),
+        (HallwayStyle::Winding, worldgen.winding_hallway_weight),
+    ];
+    styles.retain(|(_, weight)| *weight > 0.);
+
+    let mut order = vec![];
+    while !styles.is_empty() {
+        let total: f32 = styles.iter().map(|(_, weight)| weight).sum();
+        let mut pick = rng.f32() * total;
+        let index = styles
+            .iter()
+            .position(|(_, weight)| {
+                pick -= weight;
+                pick <= 0.
+            })
+            .unwrap_or(styles.len() - 1);
+        order.push(styles.remove(index).0);
+    }
+    order
+}
+
+/// creates all possible hallways of one style between room1 and room2. the hallways are ordered from room1 to room2.
+/// returns an empty list if the rooms are not positioned in a way that allows for this style
+fn generate_bent_hallways(
+    room1: &impl StructureDimensions,
+    room2: &impl StructureDimensions,
+    min_hallway_width: u32,
+    hallway_width: u32,
+    style: HallwayStyle,
+    worldgen: &WorldgenSettings,
+    rng: &mut fastrand::Rng,
+) -> Vec<Vec<RoomDimensions>> {
+    if style == HallwayStyle::LShaped {
+        // check if room1 is the left room, if not, room2 is assigned left room. -> algo needs to do less cases
+        let (flipped, left_room, right_room) =
+            maybe_flip_bl_tr(room1.anchor_grid().x < room2.anchor_grid().x, room1, room2);
+
+        // if the left room is lower than the right room on the y axis
+        //   |-->  [right]
+        //   |       |^
+        // [left] ---|
+        // then its reachable by going right->up or up-right
+        // otherwise we have this situation
+        // [left] <----
+        //  |^         |
+        //  |----- [right]
+        // and we need to go left and up to reach the other room
+        let orientations = if left_room.anchor_grid().y < right_room.anchor_grid().y {
+            [LHallwayOrientation::UpRight, LHallwayOrientation::RightUp]
+        } else {
+            [LHallwayOrientation::UpLeft, LHallwayOrientation::LeftUp]
+        };
+
+        return orientations
+            .iter()
+            .map(|orientation| {
+                let mut new_hallways = generate_l_shaped_hallway(
+                    left_room,
+                    right_room,
+                    min_hallway_width,
+                    hallway_width,
+                    orientation,
+                );
+                // if the order of initial rooms were flipped, we need to flip the order of new hallways, or else they will be connected in a wrong way later on
+                if flipped {
+                    new_hallways.reverse()
+                }
+                new_hallways
+            })
+            .collect();
+    }
+
+    // the algorithm below only knows how to go from left to right.
+    // if the rooms are further apart on the y axis, x and y are swapped before and after
+    let room1 = RoomDimensions::from_dyn_structure_dim(room1);
+    let room2 = RoomDimensions::from_dyn_structure_dim(room2);
+    let gap_x = (room2.anchor.x - room1.anchor_grid_end().x)
+        .max(room1.anchor.x - room2.anchor_grid_end().x);
+    let gap_y = (room2.anchor.y - room1.anchor_grid_end().y)
+        .max(room1.anchor.y - room2.anchor_grid_end().y);
+    let transposed = gap_y > gap_x;
+    let (room1, room2) = if transposed {
+        (transpose(&room1), transpose(&room2))
+    } else {
+        (room1, room2)
+    };
+    let flipped = room1.anchor.x > room2.anchor.x;
+    let (left_room, right_room) = if flipped { (room2, room1) } else { (room1, room2) };
+
+    let Some(corners) = bent_hallway_corners(
+        &left_room,
+        &right_room,
+        hallway_width as i32,
+        style,
+        worldgen.max_hallway_steps,
+        rng,
+    ) else {
+        return vec![];
+    };
+
+    let mut new_hallways =
+        hallway_from_corners(&left_room, &right_room, &corners, hallway_width as i32);
+    if transposed {
+        new_hallways = new_hallways.iter().map(transpose).collect();
+    }
+    if flipped {
+        new_hallways.reverse();
+    }
+    vec![new_hallways]
+}
+
+/// finds the corners of a hallway that goes from the left room to the right room.
+/// every corner is a square with the size of the hallway width. corners are placed in pairs on the same x coordinate:
+/// the hallway goes right into the first corner, then up/down into the second corner, and then right again
+///
+///                 [2]--[right]
+///                  |
+/// [left]--[0]     [1]
+///          |       |
+///         [1]-----[0]
+fn bent_hallway_corners(
+    left_room: &RoomDimensions,
+    right_room: &RoomDimensions,
+    width: i32,
+    style: HallwayStyle,
+    max_steps: usize,
+    rng: &mut fastrand::Rng,
+) -> Option<Vec<IVec2>> {
+    if left_room.height < width as usize || right_room.height < width as usize {
+        return None;
+    }
+    // the hallway starts and ends in the middle of the rooms, but has to stay inside the rooms on the y axis
+    let start_y = (left_room.center_grid().y as i32 - width / 2)
+        .clamp(left_room.anchor.y, left_room.anchor_grid_end().y - width);
+    let end_y = (right_room.center_grid().y as i32 - width / 2)
+        .clamp(right_room.anchor.y, right_room.anchor_grid_end().y - width);
+    let gap = right_room.anchor.x - left_room.anchor_grid_end().x;
+    let height_difference = (end_y - start_y).abs();
+
+    // every step needs at least one hallway width of space on both axes
+    let max_possible_steps = (gap.min(height_difference) / width).max(0) as usize;
+    let steps = match style {
+        HallwayStyle::LShaped => return None,
+        HallwayStyle::ZShaped => 1,
+        HallwayStyle::Stepped => (max_possible_steps / 2).min(max_steps),
+        HallwayStyle::Winding => max_possible_steps.min(max_steps),
+    };
+    if steps == 0 || steps > max_possible_steps || (style != HallwayStyle::ZShaped && steps < 2) {
+        return None;
+    }
+
+    // distribute the free space between the columns (x) and between the steps (y)
+    let winding = style == HallwayStyle::Winding;
+    let spaces_x = partition(gap - steps as i32 * width, steps + 1, winding, rng);
+    let spaces_y = partition(height_difference - steps as i32 * width, steps, winding, rng);
+    let direction_y = (end_y - start_y).signum();
+
+    let mut corners = vec![];
+    let mut x = left_room.anchor_grid_end().x;
+    let mut y = start_y;
+    for step in 0..steps {
+        x += spaces_x[step];
+        corners.push(IVec2::new(x, y));
+        y += direction_y * (width + spaces_y[step]);
+        corners.push(IVec2::new(x, y));
+        x += width;
+    }
+    Some(corners)
+}
+
+/// splits total into parts. if winding is false, all parts have (almost) the same size.
+/// otherwise the sizes follow perlin noise seeded from the rng, so neighbouring parts have similar sizes and the hallway meanders
+fn partition(total: i32, parts: usize, winding: bool, rng: &mut fastrand::Rng) -> Vec<i32> {
+    let total = total.max(0);
+    let mut cuts = if winding {
+        let noise = Perlin::new(rng.u32(..));
+        // perlin noise is 0 on whole numbers, so the samples start somewhere in between
+        let offset = rng.f64() * 256.;
+        let weights = (0..parts)
+            .map(|i| (1. + noise.get([offset + i as f64 * 0.7, 0.5])).max(0.1))
+            .collect_vec();
+        let sum: f64 = weights.iter().sum();
+        weights
+            .iter()
+            .scan(0., |covered, weight| {
+                *covered += weight;
+                Some((total as f64 * *covered / sum).round() as i32)
+            })
+            .take(parts - 1)
+            .collect_vec()
+    } else {
+        (1..parts).map(|i| total * i as i32 / parts as i32).collect_vec()
+    };
+    cuts.insert(0, 0);
+    cuts.push(total);
+    cuts.iter().tuple_windows().map(|(a, b)| b - a).collect()
+}
+
+/// turns the corners into rooms: the corners themselves and the straight pieces between them, from left to right
+fn hallway_from_corners(
+    left_room: &RoomDimensions,
+    right_room: &RoomDimensions,
+    corners: &[IVec2],
+    width: i32,
+) -> Vec<RoomDimensions> {
+    let corner_room = |anchor: IVec2| RoomDimensions {
+        anchor,
+        height: width as usize,
+        length: width as usize,
+    };
+    let mut new_rooms = vec![];
+    let mut previous_end_x = left_room.anchor_grid_end().x;
+    let mut previous: Option<IVec2> = None;
+
+    for &corner in corners {
+        match previous {
+            Some(previous) if previous.x == corner.x => {
+                // vertical piece
+                let start = previous.y.min(corner.y) + width;
+                let end = previous.y.max(corner.y);
+                if end > start {
+                    new_rooms.push(RoomDimensions {
+                        anchor: IVec2::new(corner.x, start),
+                        height: (end - start) as usize,
+                        length: width as usize,
+                    });
+                }
+            }
+            _ => {
+                // horizontal piece
+                if corner.x > previous_end_x {
+                    new_rooms.push(RoomDimensions {
+                        anchor: IVec2::new(previous_end_x, corner.y),
+                        height: width as usize,
+                        length: (corner.x - previous_end_x) as usize,
+                    });
+                }
+            }
+        }
+        new_rooms.push(corner_room(corner));
+        previous_end_x = corner.x + width;
+        previous = Some(corner);
+    }
+
+    // last horizontal piece into the right room
+    if let Some(last) = previous {
+        if right_room.anchor.x > previous_end_x {
+            new_rooms.push(RoomDimensions {
+                anchor: IVec2::new(previous_end_x, last.y),
+                height: width as usize,
+                length: (right_room.anchor.x - previous_end_x) as usize,
+            });
+        }
+    }
+    new_rooms
+}
+
+/// swaps the x and y axis
+fn transpose(dimensions: &RoomDimensions) -> RoomDimensions {
+    RoomDimensions {
+        anchor: IVec2::new(dimensions.anchor.y, dimensions.anchor.x),
+        height: dimensions.length,
+        length: dimensions.height,
+    }
+}
+
+fn turn_dimensions_into_room(
+    input: &impl StructureDimensions,
+    details: HallwayDetails,
+    map: &mut MapResource,
+) -> Room {
+    let mut room = Room::new(
         map.next_room_id(),
         input.length(),
         input.height(),
         input.anchor_grid(),
         false,
-    )
+    );
+    room.details.room_type = RoomType::Hallway(details);
+    room
 }
 
 /// create an AdjacentTiles object which contains tiles coordinates from both rooms.
@@ -818,6 +1478,179 @@ mod tests {
         assert!(y == 7);
     }
 
+    #[test]
+    fn test_hallways_attach_to_shapes() {
+        let worldgen = WorldgenSettings::default();
+        let mut details = Room::new(0, 20, 20, IVec2::ZERO, true).details;
+        details.shape = crate::map::room::RoomShape::Circle;
+        let dimensions = RoomDimensions {
+            anchor: IVec2::ZERO,
+            length: 20,
+            height: 20,
+        };
+        let circle = Room::new2(0, dimensions, details);
+        // the circle only reaches the right side of its bounding box in the middle six rows
+        let rect = Room::new(1, 20, 20, IVec2::new(20, 0), true);
+        assert!(are_two_rooms_adjacent(&circle, &rect, 6) == Some((6, DoorOrientation::Vertical)));
+        assert!(are_two_rooms_adjacent(&circle, &rect, 7).is_none());
+
+        // a straight hallway only covers those rows
+        let rect = Room::new(1, 20, 20, IVec2::new(30, 0), true);
+        let map_area = MapArea {
+            rooms: [(0, circle.clone()), (1, rect.clone())].into_iter().collect(),
+            ..Default::default()
+        };
+        let hallways = can_rooms_be_connected(&circle, &rect, &map_area, &worldgen).unwrap();
+        assert!(hallways.len() == 1);
+        assert!(hallways[0].anchor == IVec2::new(20, 7));
+        assert!(hallways[0].height == 6);
+    }
+
+    #[test]
+    fn test_can_rooms_be_connected() {
+        let worldgen = WorldgenSettings::default();
+        let room1 = Room::new(0, 10, 10, IVec2::new(0, 0), true);
+        let room2 = Room::new(1, 10, 10, IVec2::new(20, 0), true);
+        let mut map_area = MapArea {
+            rooms: [(0, room1.clone()), (1, room2.clone())].into_iter().collect(),
+            ..Default::default()
+        };
+        let hallways = can_rooms_be_connected(&room1, &room2, &map_area, &worldgen).unwrap();
+        assert!(hallways.len() == 1);
+        assert!(hallways[0].anchor == IVec2::new(10, 0));
+
+        // a room next to the hallway is not in the way
+        let bystander = Room::new(2, 5, 5, IVec2::new(12, 30), true);
+        map_area.rooms.insert(2, bystander);
+        let hallways = can_rooms_be_connected(&room1, &room2, &map_area, &worldgen).unwrap();
+        assert!(hallways.len() == 1);
+
+        // a wall of a room between them, no hallway can go around it
+        let blocker = Room::new(2, 5, 200, IVec2::new(12, -100), true);
+        map_area.rooms.insert(2, blocker);
+        assert!(can_rooms_be_connected(&room1, &room2, &map_area, &worldgen).is_none());
+    }
+
+    #[test]
+    fn test_bent_hallways() {
+        let worldgen = WorldgenSettings::default();
+        let mut rng = fastrand::Rng::with_seed(0);
+        // rooms that are far apart on the x axis and have no overlap on the y axis
+        let room1 = Room::new(0, 10, 10, IVec2::new(0, 0), true);
+        let room2 = Room::new(1, 10, 10, IVec2::new(60, 40), true);
+
+        for style in [
+            HallwayStyle::ZShaped,
+            HallwayStyle::Stepped,
+            HallwayStyle::Winding,
+        ] {
+            let candidates =
+                generate_bent_hallways(&room1, &room2, 4, 4, style, &worldgen, &mut rng);
+            assert!(!candidates.is_empty());
+            for hallways in candidates {
+                // every piece touches the next one, from room1 to room2
+                assert!(are_two_rooms_adjacent(&room1, &hallways[0], 0).is_some());
+                assert!(are_two_rooms_adjacent(hallways.last().unwrap(), &room2, 0).is_some());
+                for (a, b) in hallways.iter().tuple_windows() {
+                    assert!(are_two_rooms_adjacent(a, b, 0).is_some());
+                }
+            }
+        }
+
+        // the rooms are too close on the y axis for a step
+        let room2 = Room::new(1, 10, 10, IVec2::new(60, 2), true);
+        let candidates = generate_bent_hallways(
+            &room1,
+            &room2,
+            4,
+            4,
+            HallwayStyle::ZShaped,
+            &worldgen,
+            &mut rng,
+        );
+        assert!(candidates.is_empty());
+    }
+
+    #[test]
+    fn test_hallway_details() {
+        let worldgen = WorldgenSettings::default();
+        let mut map = MapResource::default();
+        map.map_area_mut()
+            .rooms
+            .insert(0, Room::new(0, 20, 20, IVec2::new(0, 0), true));
+        map.map_area_mut()
+            .rooms
+            .insert(1, Room::new(1, 20, 20, IVec2::new(40, 60), true));
+        let connection = RoomConnection::new(1, 0, RoomConnectionType::Separated);
+        let mut new_connections = vec![];
+        let result =
+            create_and_connect_hallways(&connection, &mut new_connections, &mut map, &worldgen);
+        assert!(result.is_ok());
+
+        let hallways = map.map_area().hallways_of_connection(1, 0);
+        assert!(!hallways.is_empty());
+        assert!(hallways.len() == map.map_area().hallways().len());
+        for (index, hallway) in hallways.iter().enumerate() {
+            let details = hallway.hallway_details().unwrap();
+            assert!(details.segments.len() == 1);
+            assert!(details.segments[0].connection == (1, 0));
+            assert!(details.segments[0].index == index);
+            assert!(details.width > 0);
+            assert!(!hallway.details.is_main);
+        }
+        // asking from the other side gives the segments in the other order
+        let reversed = map.map_area().hallways_of_connection(0, 1);
+        assert!(reversed.first().unwrap().id() == hallways.last().unwrap().id());
+        assert!(map.map_area().hallway_details(0).is_none());
+    }
+
+    #[test]
+    fn test_merge_hallways() {
+        let worldgen = WorldgenSettings::default();
+        let hallway = |id: usize, anchor: IVec2, connection: (usize, usize)| {
+            let mut room = Room::new(id, 10, 6, anchor, false);
+            room.details.room_type = RoomType::Hallway(HallwayDetails::new(connection, 0, 6));
+            room
+        };
+        // two rooms on the left, two rooms on the right, and two parallel hallways between them
+        // [0]=[2]=[1]
+        // [3]=[5]=[4]
+        let mut map = MapResource::default();
+        let rooms = [
+            Room::new(0, 10, 6, IVec2::new(0, 6), true),
+            Room::new(1, 10, 6, IVec2::new(20, 6), true),
+            hallway(2, IVec2::new(10, 6), (0, 1)),
+            Room::new(3, 10, 6, IVec2::new(0, 0), true),
+            Room::new(4, 10, 6, IVec2::new(20, 0), true),
+            hallway(5, IVec2::new(10, 0), (3, 4)),
+        ];
+        for room in rooms {
+            map.map_area_mut().rooms.insert(room.id(), room);
+        }
+        let mut connections = vec![];
+        for (a, b) in [(0, 2), (2, 1), (3, 5), (5, 4)] {
+            let room1 = &map.map_area().rooms[&a];
+            let room2 = &map.map_area().rooms[&b];
+            let ctype = find_out_connection_type(room1, room2, map.map_area(), &worldgen);
+            connections.push(RoomConnection::new(a, b, ctype));
+        }
+
+        assert!(find_hallways_to_merge(map.map_area(), &connections) == Some((2, 5)));
+        merge_two_rooms(2, 5, &mut map, &mut connections, &worldgen);
+        assert!(find_hallways_to_merge(map.map_area(), &connections).is_none());
+
+        let merged = &map.map_area().rooms[&2];
+        assert!(!map.map_area().rooms.contains_key(&5));
+        assert!(merged.anchor_grid() == IVec2::new(10, 0));
+        assert!(merged.height() == 12 && merged.length() == 10);
+        assert!(connections.len() == 4);
+        assert!(connections.iter().all(|c| c.room1_id != 5 && c.room2_id != 5));
+        assert!(connections.iter().all(|c| c.is_adjacent()));
+        // the merged hallway is part of both chains
+        assert!(map.map_area().hallways_of_connection(0, 1)[0].id() == 2);
+        assert!(map.map_area().hallways_of_connection(3, 4)[0].id() == 2);
+    }
+
     /* #[test]
     fn test_reduce_connections() {
         let connections = vec![
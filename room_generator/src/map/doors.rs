@@ -0,0 +1,137 @@
+use bevy::{prelude::*, sprite::Anchor};
+
+use super::{
+    generation::{MapResource, WorldgenSettings},
+    room::DoorOrientation,
+};
+
+/// a door between two rooms, written by `carve_doors`.
+/// the door is `width` tiles long and `depth` tiles thick: one tile in each room, plus the passages into shaped rooms
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component, Reflect)]
+pub struct Door {
+    pub position: IVec2, // bottom left tile of the door in global coordinates
+    pub width: u32,
+    pub depth: u32, // 2, unless the door has a passage into a shaped room
+    pub orientation: DoorOrientation,
+    pub rooms: (usize, usize),
+    pub is_locked: bool, // the connection needs a key (see progression.rs)
+    pub is_one_way: bool, // a drop, can only be passed from rooms.0 to rooms.1
+    pub is_boss_gate: bool, // opens after the boss has been defeated
+}
+impl Door {
+    /// size of the door in tiles
+    pub fn size(&self) -> UVec2 {
+        match self.orientation {
+            DoorOrientation::Vertical => UVec2::new(self.depth, self.width),
+            DoorOrientation::Horziontal => UVec2::new(self.width, self.depth),
+        }
+    }
+
+    /// every tile of the door in global coordinates
+    pub fn tiles(&self) -> impl Iterator<Item = IVec2> + '_ {
+        let size = self.size().as_ivec2();
+        (0..size.y).flat_map(move |y| (0..size.x).map(move |x| self.position + IVec2::new(x, y)))
+    }
+
+    pub fn center_world(&self, tile_size: UVec2) -> Vec2 {
+        (self.position.as_vec2() + self.size().as_vec2() / 2.) * tile_size.as_vec2()
+    }
+}
+
+/// the state of a spawned door. game code changes it, the sprite follows.
+/// the state is only cosmetic: it does not change the tiles of the map, so a locked door can still be walked through.
+/// games that want doors to block movement check `is_passable` themselves
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component, Reflect)]
+pub enum DoorState {
+    Open,
+    Closed,
+    Locked,
+}
+impl DoorState {
+    /// returns false if the door is locked
+    pub fn open(&mut self) -> bool {
+        if *self == DoorState::Locked {
+            return false;
+        }
+        *self = DoorState::Open;
+        true
+    }
+    pub fn close(&mut self) {
+        if *self == DoorState::Open {
+            *self = DoorState::Closed;
+        }
+    }
+    pub fn lock(&mut self) {
+        *self = DoorState::Locked;
+    }
+    pub fn unlock(&mut self) {
+        if *self == DoorState::Locked {
+            *self = DoorState::Closed;
+        }
+    }
+    pub fn is_passable(&self) -> bool {
+        *self == DoorState::Open
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            DoorState::Open => Color::rgba(0.55, 0.35, 0.17, 0.3),
+            DoorState::Closed => Color::rgb(0.55, 0.35, 0.17),
+            DoorState::Locked => Color::rgb(0.6, 0.1, 0.1),
+        }
+    }
+}
+
+/// spawns an entity for every door of the map. locked doors and boss gates start locked, every other door starts open
+pub fn spawn_doors(mut commands: Commands, map: Res<MapResource>, worldgen: Res<WorldgenSettings>) {
+    for door in map.map_area().doors.iter() {
+        let state = if door.is_locked || door.is_boss_gate {
+            DoorState::Locked
+        } else {
+            DoorState::Open
+        };
+        commands.spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    color: state.color(),
+                    custom_size: Some(door.size().as_vec2() * worldgen.tile_size.as_vec2()),
+                    anchor: Anchor::BottomLeft,
+                    ..default()
+                },
+                transform: Transform::from_translation(
+                    (door.position * worldgen.tile_size.as_ivec2())
+                        .as_vec2()
+                        .extend(-5.),
+                ),
+                ..default()
+            },
+            *door,
+            state,
+            Name::new(format!("Door: {} - {}", door.rooms.0, door.rooms.1)),
+        ));
+    }
+}
+
+pub fn update_door_sprites(mut doors: Query<(&DoorState, &mut Sprite), Changed<DoorState>>) {
+    for (state, mut sprite) in doors.iter_mut() {
+        sprite.color = state.color();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_door_state() {
+        let mut state = DoorState::Open;
+        state.close();
+        assert!(state == DoorState::Closed);
+        state.lock();
+        assert!(!state.open());
+        assert!(!state.is_passable());
+        state.unlock();
+        assert!(state.open());
+        assert!(state.is_passable());
+    }
+}
@@ -0,0 +1,246 @@
+use std::fs;
+
+use bevy::prelude::*;
+use itertools::Itertools;
+
+use super::{
+    connecting::RoomConnectionType,
+    generation::{MapArea, MapResource, WorldgenSettings},
+    graphing::MyGraph,
+    room::StructureDimensions,
+    world_tiles::WorldTiles,
+};
+
+/// writes the delaunay graph, the MST and the reassembled graph to disk as Graphviz DOT and GraphML files.
+/// nodes and edges are sorted by id, so files from different seeds can be diffed with standard tools
+///
+/// this runs right after `make_graphs`. later stages replace the reassembled graph with the graph of the hallways
+pub fn export_graphs(map: &MapResource, worldgen: &WorldgenSettings) {
+    if !worldgen.export_graphs {
+        return;
+    }
+    let Some(room_graph) = map.map_area().graph.as_ref() else {
+        return;
+    };
+
+    let path = "room_generator/exports";
+    if let Err(error) = fs::create_dir_all(path) {
+        warn!("could not create export directory. log: {:?}", error);
+        return;
+    }
+
+    for (name, graph) in [
+        ("delaunay", &room_graph.delaunay),
+        ("mst", &room_graph.mst),
+        ("reassembled", &room_graph.reassembled_graph),
+    ] {
+        let file_name = format!("{}/seed_{}_{}", path, worldgen.global_seed, name);
+        let dot = graph_to_dot(name, graph, map.map_area());
+        let graphml = graph_to_graphml(name, graph, map.map_area());
+        if let Err(error) = fs::write(format!("{file_name}.dot"), dot)
+            .and_then(|_| fs::write(format!("{file_name}.graphml"), graphml))
+        {
+            warn!(
+                "could not write graph export {}. log: {:?}",
+                file_name, error
+            );
+        }
+    }
+}
+
+/// writes the unified world grid to disk as text, one character per tile
+pub fn export_world_tiles(map: &MapResource, worldgen: &WorldgenSettings) {
+    if !worldgen.export_world_tiles {
+        return;
+    }
+    let Some(world_tiles) = map.map_area().world_tiles.as_ref() else {
+        return;
+    };
+
+    let path = "room_generator/exports";
+    let file_name = format!("{}/seed_{}_tiles.txt", path, worldgen.global_seed);
+    if let Err(error) = fs::create_dir_all(path)
+        .and_then(|_| fs::write(&file_name, world_tiles_to_text(world_tiles)))
+    {
+        warn!(
+            "could not write tile export {}. log: {:?}",
+            file_name, error
+        );
+    }
+}
+
+/// one character per tile (see `TILE_PROPERTIES`) and a space where there is no room. the top row is written first
+pub fn world_tiles_to_text(world_tiles: &WorldTiles) -> String {
+    let size = world_tiles.size().as_ivec2();
+    let mut output = String::new();
+    for y in (0..size.y).rev() {
+        for x in 0..size.x {
+            let tile = world_tiles.get(world_tiles.anchor() + IVec2::new(x, y));
+            output.push(tile.map_or(' ', |tile| tile.properties().symbol));
+        }
+        output.push('\n');
+    }
+    output
+}
+
+/// exports a graph in the Graphviz DOT format
+pub fn graph_to_dot(name: &str, graph: &MyGraph, map_area: &MapArea) -> String {
+    let mut output = format!("graph {name} {{\n");
+    for node in node_attributes(graph, map_area) {
+        output += &format!(
+            "    {} [label=\"{}\", room_type=\"{}\", main_path={}, length={}, height={}];\n",
+            node.id, node.id, node.room_type, node.main_path, node.size.x, node.size.y
+        );
+    }
+    for edge in edge_attributes(graph, map_area) {
+        output += &format!(
+            "    {} -- {} [weight={}, connection=\"{}\"];\n",
+            edge.room1, edge.room2, edge.weight, edge.connection
+        );
+    }
+    output += "}\n";
+    output
+}
+
+/// exports a graph in the GraphML format
+pub fn graph_to_graphml(name: &str, graph: &MyGraph, map_area: &MapArea) -> String {
+    let mut output = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n  \
+         <key id=\"room_type\" for=\"node\" attr.name=\"room_type\" attr.type=\"string\"/>\n  \
+         <key id=\"main_path\" for=\"node\" attr.name=\"main_path\" attr.type=\"boolean\"/>\n  \
+         <key id=\"length\" for=\"node\" attr.name=\"length\" attr.type=\"int\"/>\n  \
+         <key id=\"height\" for=\"node\" attr.name=\"height\" attr.type=\"int\"/>\n  \
+         <key id=\"weight\" for=\"edge\" attr.name=\"weight\" attr.type=\"double\"/>\n  \
+         <key id=\"connection\" for=\"edge\" attr.name=\"connection\" attr.type=\"string\"/>\n",
+    );
+    output += &format!("  <graph id=\"{name}\" edgedefault=\"undirected\">\n");
+    for node in node_attributes(graph, map_area) {
+        output += &format!(
+            "    <node id=\"{}\">\n      \
+             <data key=\"room_type\">{}</data>\n      \
+             <data key=\"main_path\">{}</data>\n      \
+             <data key=\"length\">{}</data>\n      \
+             <data key=\"height\">{}</data>\n    \
+             </node>\n",
+            node.id, node.room_type, node.main_path, node.size.x, node.size.y
+        );
+    }
+    for edge in edge_attributes(graph, map_area) {
+        output += &format!(
+            "    <edge source=\"{}\" target=\"{}\">\n      \
+             <data key=\"weight\">{}</data>\n      \
+             <data key=\"connection\">{}</data>\n    \
+             </edge>\n",
+            edge.room1, edge.room2, edge.weight, edge.connection
+        );
+    }
+    output += "  </graph>\n</graphml>\n";
+    output
+}
+
+struct NodeAttributes {
+    id: usize,
+    room_type: String,
+    main_path: bool,
+    size: UVec2,
+}
+
+struct EdgeAttributes {
+    room1: usize,
+    room2: usize,
+    weight: f32,
+    connection: &'static str,
+}
+
+fn node_attributes(graph: &MyGraph, map_area: &MapArea) -> Vec<NodeAttributes> {
+    let main_path_rooms = map_area
+        .graph
+        .as_ref()
+        .map(|g| g.main_path_rooms.clone())
+        .unwrap_or_default();
+
+    graph
+        .nodes()
+        .sorted()
+        .map(|id| {
+            let room = map_area.rooms.get(&id);
+            NodeAttributes {
+                id,
+                room_type: room.map_or("Unknown".into(), |r| r.details.room_type.name().into()),
+                main_path: main_path_rooms.contains(&id),
+                size: room.map_or(UVec2::ZERO, |r| {
+                    UVec2::new(r.length() as u32, r.height() as u32)
+                }),
+            }
+        })
+        .collect()
+}
+
+fn edge_attributes(graph: &MyGraph, map_area: &MapArea) -> Vec<EdgeAttributes> {
+    graph
+        .all_edges()
+        .map(|(a, b, weight)| (a.min(b), a.max(b), *weight))
+        .sorted_by_key(|(a, b, _)| (*a, *b))
+        .map(|(room1, room2, weight)| {
+            // edges of the delaunay graph and the MST might not have been turned into a connection
+            let connection = map_area
+                .connections
+                .iter()
+                .flatten()
+                .find(|c| {
+                    (c.room1_id == room1 && c.room2_id == room2)
+                        || (c.room1_id == room2 && c.room2_id == room1)
+                })
+                .map_or("None", |c| c.data.variant_name());
+            EdgeAttributes {
+                room1,
+                room2,
+                weight,
+                connection,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{connecting::RoomConnection, room::Room};
+    use bevy::utils::HashMap;
+
+    #[test]
+    fn test_graph_to_dot() {
+        let mut rooms = HashMap::new();
+        rooms.insert(0, Room::new(0, 10, 5, IVec2::new(0, 0), true));
+        rooms.insert(1, Room::new(1, 5, 5, IVec2::new(10, 0), true));
+        let mut graph = MyGraph::default();
+        graph.add_edge(1, 0, 2.5);
+        let map_area = MapArea {
+            rooms,
+            connections: Some(vec![RoomConnection::new(
+                0,
+                1,
+                RoomConnectionType::Separated,
+            )]),
+            ..Default::default()
+        };
+
+        let dot = graph_to_dot("test", &graph, &map_area);
+        assert!(dot.starts_with("graph test {"));
+        assert!(dot.contains(
+            "0 [label=\"0\", room_type=\"Normal\", main_path=false, length=10, height=5];"
+        ));
+        assert!(dot.contains("0 -- 1 [weight=2.5, connection=\"Separated\"];"));
+    }
+
+    #[test]
+    fn test_world_tiles_to_text() {
+        let mut map_area = MapResource::default().map_area().clone();
+        let mut room = Room::new(0, 4, 3, IVec2::new(-2, 0), true);
+        room.fill_edges();
+        map_area.rooms.insert(0, room);
+        let world_tiles = WorldTiles::from_map_area(&map_area).unwrap();
+        assert!(world_tiles_to_text(&world_tiles) == "####\n#..#\n####\n");
+    }
+}
@@ -1,8 +1,10 @@
 use super::{
-    connecting::RoomConnection,
+    connecting::{ConnectionAttribute, RoomConnection},
+    doors::Door,
     graphing::RoomGraph,
     presets::{self, RoomPresetResource},
-    room::{Room, StructureDimensions}
+    room::{HallwayDetails, Room, StructureDimensions, Tile},
+    world_tiles::WorldTiles,
 };
 use bevy::{prelude::*, utils::HashMap};
 use delaunator::Triangulation;
@@ -15,13 +17,7 @@ pub struct MapResource {
 impl Default for MapResource {
     fn default() -> Self {
         Self {
-            map_area: MapArea {
-                rooms: HashMap::new(),
-                initial_connections: Vec::new(),
-                triangulation: None,
-                graph: None,
-                connections: None,
-            },
+            map_area: MapArea::default(),
         }
     }
 }
@@ -33,18 +29,66 @@ impl MapResource {
         &mut self.map_area
     }
     pub fn next_room_id(&mut self) -> usize {
-        let current = self.map_area.rooms.len();
-        current
+        // rooms can be removed (i.e. merged hallways), so the amount of rooms is not always a free id
+        self.map_area.rooms.keys().max().map_or(0, |id| id + 1)
     }
 }
 
-#[derive(Debug, Clone, Component)]
+#[derive(Debug, Clone, Default, Component)]
 pub struct MapArea {
     pub rooms: HashMap<usize, Room>,
     pub initial_connections: Vec<(usize, usize)>,
+    pub connection_attributes: HashMap<(usize, usize), Vec<ConnectionAttribute>>, // (room1_id, room2_id) -> attributes. from presets
     pub triangulation: Option<Triangulation>,
     pub graph: Option<RoomGraph>,
     pub connections: Option<Vec<RoomConnection>>,
+    pub tile_index: TileIndex,
+    pub world_tiles: Option<WorldTiles>, // only built if `WorldgenSettings::unified_world_grid` is set
+    pub doors: Vec<Door>,                // written by carve_doors
+}
+
+/// lookup from a global tile coordinate to the rooms that contain it and the local coordinate inside each room.
+/// the index is only used once it has been built with `MapArea::rebuild_tile_index`. until then every lookup loops over all rooms.
+/// rooms that are added or removed with `MapArea::insert_room` and `MapArea::remove_room` keep the index up to date
+#[derive(Debug, Clone, Default)]
+pub struct TileIndex {
+    tiles: HashMap<IVec2, Vec<(usize, UVec2)>>, // sorted by room id, so overlapping rooms are all found
+    is_built: bool,
+}
+impl TileIndex {
+    pub fn is_built(&self) -> bool {
+        self.is_built
+    }
+
+    /// adds every tile of the room. Void tiles are not part of the room
+    fn add_room(&mut self, room: &Room) {
+        for y in 0..room.height() as u32 {
+            for x in 0..room.length() as u32 {
+                let local = UVec2::new(x, y);
+                if room.get_tile(local) == Some(&Tile::Void) {
+                    continue;
+                }
+                let rooms = self.tiles.entry(room.local_to_global(local)).or_default();
+                let index = rooms.partition_point(|(id, _)| *id < room.id());
+                rooms.insert(index, (room.id(), local));
+            }
+        }
+    }
+
+    fn remove_room(&mut self, room: &Room) {
+        for y in 0..room.height() as u32 {
+            for x in 0..room.length() as u32 {
+                let global = room.local_to_global(UVec2::new(x, y));
+                let Some(rooms) = self.tiles.get_mut(&global) else {
+                    continue;
+                };
+                rooms.retain(|(id, _)| *id != room.id());
+                if rooms.is_empty() {
+                    self.tiles.remove(&global);
+                }
+            }
+        }
+    }
 }
 
 impl MapArea {
@@ -70,14 +114,126 @@ impl MapArea {
             / self.rooms.len() as f32
     } */
 
+    /// all rooms that were created as hallways, sorted by id
+    pub fn hallways(&self) -> Vec<&Room> {
+        self.rooms
+            .values()
+            .filter(|r| r.is_hallway())
+            .sorted_by_key(|r| r.id())
+            .collect()
+    }
+
+    pub fn hallway_details(&self, room_id: usize) -> Option<&HallwayDetails> {
+        self.rooms.get(&room_id)?.hallway_details()
+    }
+
+    /// the hallway segments (and hubs) that were built for the connection between room1 and room2, from room1 to room2
+    pub fn hallways_of_connection(&self, room1_id: usize, room2_id: usize) -> Vec<&Room> {
+        let segments = self
+            .rooms
+            .values()
+            .filter_map(|r| Some((r, *r.hallway_details()?.segment_of(room1_id, room2_id)?)))
+            .sorted_by_key(|(_, segment)| segment.index)
+            .collect_vec();
+        let flipped = segments
+            .first()
+            .is_some_and(|(_, segment)| segment.connection.0 != room1_id);
+        let segments = segments.into_iter().map(|(room, _)| room);
+        if flipped {
+            segments.rev().collect()
+        } else {
+            segments.collect()
+        }
+    }
+
+    /// the tile at a global position, if any room contains that position
+    pub fn global_tile(&self, point: IVec2) -> Option<&Tile> {
+        let (room_id, local) = self.tile_at(point)?;
+        self.rooms.get(&room_id)?.get_tile(local)
+    }
+
+    /// sets the tile at a global position. returns false if no room contains that position
+    pub fn set_global_tile(&mut self, point: IVec2, tile: Tile) -> bool {
+        let Some((room_id, local)) = self.tile_at(point) else {
+            return false;
+        };
+        self.rooms
+            .get_mut(&room_id)
+            .is_some_and(|room| room.set_tile(local, tile))
+    }
+
+    /// the room that contains the global position, and the local position inside that room
+    pub fn tile_at(&self, point: IVec2) -> Option<(usize, UVec2)> {
+        if self.tile_index.is_built() {
+            return self.tile_index.tiles.get(&point)?.first().copied();
+        }
+        let room = &self.rooms[&self.point_to_room_linear((point.x, point.y))?];
+        Some((room.id(), room.global_to_local(point)?))
+    }
+
+    /// if rooms overlap, only the room with the lowest id is returned. see `rooms_at` for every room
     pub fn point_to_room(&self, point: (i32, i32)) -> Option<usize> {
-        // probably should  only iter through non-main rooms. not implemented for now
-        for room in self.rooms.values() {
-            if room.is_point_inside(point) {
-                return Some(room.id());
-            }
+        self.tile_at(IVec2::new(point.0, point.1))
+            .map(|(room_id, _)| room_id)
+    }
+
+    /// every room that contains the global position, sorted by id. overlapping rooms are found too
+    pub fn rooms_at(&self, point: (i32, i32)) -> Vec<usize> {
+        if self.tile_index.is_built() {
+            return self
+                .tile_index
+                .tiles
+                .get(&IVec2::new(point.0, point.1))
+                .map(|rooms| rooms.iter().map(|(id, _)| *id).collect())
+                .unwrap_or_default();
+        }
+        self.rooms
+            .values()
+            .filter(|room| room.is_point_inside(point))
+            .map(|room| room.id())
+            .sorted()
+            .collect()
+    }
+
+    /// loops over every room. only used while the tile index is not built
+    fn point_to_room_linear(&self, point: (i32, i32)) -> Option<usize> {
+        // if rooms overlap, the one with the lowest id wins. the tile index does the same
+        self.rooms
+            .values()
+            .filter(|room| room.is_point_inside(point))
+            .map(|room| room.id())
+            .min()
+    }
+
+    /// builds the tile index from scratch. needs to be called after rooms were moved
+    pub fn rebuild_tile_index(&mut self) {
+        let mut tile_index = TileIndex {
+            tiles: HashMap::new(),
+            is_built: true,
+        };
+        for room in self.rooms.values().sorted_by_key(|r| r.id()) {
+            tile_index.add_room(room);
+        }
+        self.tile_index = tile_index;
+    }
+
+    /// inserts a room (or replaces the room with the same id) and keeps the tile index up to date
+    pub fn insert_room(&mut self, room: Room) {
+        self.remove_room(room.id());
+        if self.tile_index.is_built() {
+            self.tile_index.add_room(&room);
+        }
+        self.rooms.insert(room.id(), room);
+    }
+
+    /// removes a room and keeps the tile index up to date
+    pub fn remove_room(&mut self, room_id: usize) -> Option<Room> {
+        let room = self.rooms.remove(&room_id)?;
+        if self.tile_index.is_built() {
+            // rooms that overlapped the removed room are still listed for those tiles
+            self.tile_index.remove_room(&room);
         }
-        None
+        Some(room)
     }
 }
 
@@ -89,6 +245,84 @@ impl WorldgenRng {
     }
 }
 
+/// results of the checks that run after a map has been generated
+#[derive(Debug, Clone, Default, Reflect, Resource)]
+#[reflect(Resource)]
+pub struct GenerationReport {
+    pub unreachable_rooms: Vec<usize>,
+    pub repaired_rooms: Vec<usize>,
+    pub gated_rooms: Vec<usize>, // behind secret, locked or one-way connections. these are not carved open
+    pub error: Option<WorldgenError>, // set if the last generation failed. the previous map is kept in that case
+    pub warnings: Vec<WorldgenError>, // problems that did not stop the generation
+}
+
+/// everything that can go wrong while generating a map.
+/// stages return this instead of panicking, and the stages after a failed one are skipped
+#[derive(Debug, Clone, PartialEq, Reflect)]
+pub enum WorldgenError {
+    PresetMissing(String),      // no preset of this type (or with this name) was loaded
+    UnknownPresetRoom(String),  // a preset connection uses a room name that is not part of the preset
+    SeparationFailed(usize),    // rooms still overlap after this many iterations
+    EmptyTriangulation,         // not enough main rooms to build a graph from
+    DisconnectedGraph,          // there is no path between the start and the end of the map
+    MissingStageOutput(String), // a stage ran before the stage it depends on
+    UnknownRoom(usize),         // a graph, connection or tile refers to a room that does not exist
+}
+impl std::fmt::Display for WorldgenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WorldgenError::PresetMissing(name) => write!(f, "no preset found for \"{name}\""),
+            WorldgenError::UnknownPresetRoom(name) => {
+                write!(f, "preset connection uses unknown room \"{name}\"")
+            }
+            WorldgenError::SeparationFailed(iterations) => {
+                write!(f, "rooms still overlap after {iterations} separation iterations")
+            }
+            WorldgenError::EmptyTriangulation => {
+                write!(f, "not enough main rooms to make a graph")
+            }
+            WorldgenError::DisconnectedGraph => {
+                write!(f, "start and end room are not connected")
+            }
+            WorldgenError::MissingStageOutput(output) => {
+                write!(f, "{output} is missing, stages ran in the wrong order")
+            }
+            WorldgenError::UnknownRoom(id) => write!(f, "there is no room with id {id}"),
+        }
+    }
+}
+impl std::error::Error for WorldgenError {}
+
+/// every stage that can fail is piped into this system
+pub fn handle_worldgen_error(
+    In(result): In<Result<(), WorldgenError>>,
+    mut report: ResMut<GenerationReport>,
+) {
+    if let Err(error) = result {
+        error!("map generation failed: {error}");
+        report.error = Some(error);
+    }
+}
+
+/// run condition for the stages. once a stage failed, nothing else is generated
+pub fn generation_ok(report: Res<GenerationReport>) -> bool {
+    report.error.is_none()
+}
+
+/// the report only describes the latest generation, this is the only place it is cleared
+pub fn clear_generation_report(mut report: ResMut<GenerationReport>) {
+    *report = GenerationReport::default();
+}
+
+/// a failed generation leaves a half finished map in the MapResource.
+/// the map that is still on screen is put back, so everything that reads the resource stays consistent
+pub fn restore_previous_map(mut map: ResMut<MapResource>, previous_map: Query<&MapArea>) {
+    map.map_area = match previous_map.get_single() {
+        Ok(previous_map) => previous_map.clone(),
+        Err(_) => MapResource::default().map_area,
+    };
+}
+
 #[derive(Debug, Clone, Reflect, Resource)]
 #[reflect(Resource)]
 pub struct WorldgenSettings {
@@ -99,11 +333,28 @@ pub struct WorldgenSettings {
     pub snap_to: u32, // doesnt do anything anynmore
     pub main_room_threshold_multiplier: f32,
     pub separation_factor: f32,
-    pub graph_reassembly_percentage: f32,
-    pub clear_unconnected_rooms: bool,
+    pub loops_amount: usize,
+    pub min_loop_length: usize,
+    pub max_loop_length: usize,
+    pub loops_touch_main_path: bool,
+    pub locks_amount: usize,
+    pub secrets_amount: usize, // connections of loops that are hidden behind a wall
+    pub one_way_amount: usize, // connections of loops that become drops
+    pub boss_gate: bool,       // the last connection of the main path opens after the boss
+    pub export_graphs: bool,
     pub min_passage_width: u32,
     pub max_passage_width: u32,
     pub threshold: u32,
+    // how likely each hallway shape is picked when two rooms cannot be connected by a straight hallway. 0 disables a shape
+    pub l_hallway_weight: f32,
+    pub z_hallway_weight: f32,
+    pub stepped_hallway_weight: f32,
+    pub winding_hallway_weight: f32,
+    pub max_hallway_steps: usize, // maximum amount of steps for stepped and winding hallways
+    pub hub_rooms: bool, // turn hallway bends and crossings into hub rooms
+    pub hub_size: u32,   // length and height of a hub room. the aesthetics are set in worldgen/config.ron
+    pub unified_world_grid: bool, // merge all rooms into one grid after postprocessing. rendering uses it when it exists
+    pub export_world_tiles: bool, // write the unified world grid as text next to the graph exports
 }
 impl Default for WorldgenSettings {
     fn default() -> Self {
@@ -114,11 +365,27 @@ impl Default for WorldgenSettings {
             spawn_range: 100,
             snap_to: 1,
             main_room_threshold_multiplier: -1.0,
-            graph_reassembly_percentage: 0.30,
+            loops_amount: 2,
+            min_loop_length: 3,
+            max_loop_length: 6,
+            loops_touch_main_path: false,
+            locks_amount: 2,
+            secrets_amount: 1,
+            one_way_amount: 1,
+            boss_gate: true,
+            export_graphs: false,
             min_passage_width: 6,
             max_passage_width: 12,
             threshold: 9999,
-            clear_unconnected_rooms: true,
+            l_hallway_weight: 1.,
+            z_hallway_weight: 1.,
+            stepped_hallway_weight: 0.5,
+            winding_hallway_weight: 0.5,
+            max_hallway_steps: 4,
+            hub_rooms: false,
+            hub_size: 14,
+            unified_world_grid: false,
+            export_world_tiles: false,
             separation_factor: 2.,
         };
         settings
@@ -136,20 +403,22 @@ pub struct RoomGenerationSettings {
 }
 
 pub fn generate_rooms(
-    worldgen: Res<WorldgenSettings>,
-    mut map: ResMut<MapResource>,
-    mut rng: ResMut<WorldgenRng>,
-    presets: Res<RoomPresetResource>,
-) {
+    map: &mut MapResource,
+    worldgen: &WorldgenSettings,
+    rng: &mut WorldgenRng,
+    presets: &RoomPresetResource,
+) -> Result<(), WorldgenError> {
     let mut rooms = HashMap::new();
     let mut room_id_count = 0usize;
     let mut initial_connections = Vec::new();
+    let mut connection_attributes = HashMap::new();
 
     for _ in 0..worldgen.presets_to_spawn {
-        let preset = &presets.get_preset_by_type("normal", &mut rng);
-        let preset = preset.clone().unwrap();
+        let preset = presets
+            .get_preset_by_type("normal", rng)
+            .ok_or_else(|| WorldgenError::PresetMissing("normal".into()))?;
 
-        let mut preset_rooms = presets::generate_rooms_from_preset(&preset, &mut rng);
+        let mut preset_rooms = presets::generate_rooms_from_preset(&preset, rng)?;
 
         // world position
         let x = rng.i32(-worldgen.spawn_range..=worldgen.spawn_range);
@@ -167,23 +436,26 @@ pub fn generate_rooms(
             room_id_count += 1;
         }
 
-        preset_rooms.1.iter_mut().for_each(|c| {
-            c.0 += previous_max_id;
-            c.1 += previous_max_id;
-        });
-
-        initial_connections.append(&mut preset_rooms.1);
+        for (room1, room2, attributes) in preset_rooms.1 {
+            let ids = (room1 + previous_max_id, room2 + previous_max_id);
+            initial_connections.push(ids);
+            if !attributes.is_empty() {
+                connection_attributes.insert(ids, attributes);
+            }
+        }
     }
 
     map.map_area = MapArea {
         rooms,
         initial_connections,
-        triangulation: None,
-        graph: None,
-        connections: None,
+        connection_attributes,
+        ..Default::default()
     };
 
+    map.map_area.rebuild_tile_index();
+
     println!("init connections: {:?}", map.map_area.initial_connections);
+    Ok(())
 }
 
 /* pub fn custom_rooms(mut map: ResMut<MapResource>, worldgen: Res<WorldgenSettings>) {
@@ -221,7 +493,7 @@ pub fn generate_rooms(
     };
 } */
 
-pub fn determine_main_rooms(mut map: ResMut<MapResource>, _worldgen: Res<WorldgenSettings>) {
+pub fn determine_main_rooms(map: &mut MapResource) {
     //let mean_room_size = map.map_area().mean_room_size_grid();
     for room in map.map_area_mut().rooms.values_mut() {
         room.details.is_main = true;
@@ -238,6 +510,7 @@ pub fn despawn_chunks(
     events: EventReader<RegenerateRoomsEvent>,
     query_rooms: Query<Entity, With<Room>>,
     query_map_areas: Query<Entity, With<MapArea>>,
+    query_doors: Query<Entity, With<Door>>,
     query_text: Query<Entity, With<Text>>,
     mut commands: Commands,
 ) {
@@ -247,6 +520,9 @@ pub fn despawn_chunks(
             commands.entity(ent).despawn_recursive();
         }
         for ent in query_map_areas.iter() {
+            commands.entity(ent).despawn_recursive();
+        }
+        for ent in query_doors.iter() {
             commands.entity(ent).despawn();
         }
         for ent in query_text.iter() {
@@ -254,3 +530,37 @@ pub fn despawn_chunks(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tile_index() {
+        let mut map_area = MapResource::default().map_area().clone();
+        map_area.insert_room(Room::new(0, 10, 10, IVec2::new(0, 0), true));
+        map_area.insert_room(Room::new(1, 10, 10, IVec2::new(10, 0), true));
+        // without an index every room is checked
+        assert!(map_area.tile_at(IVec2::new(12, 3)) == Some((1, UVec2::new(2, 3))));
+
+        map_area.rebuild_tile_index();
+        assert!(map_area.tile_at(IVec2::new(12, 3)) == Some((1, UVec2::new(2, 3))));
+        assert!(map_area.tile_at(IVec2::new(20, 3)).is_none());
+
+        // an overlapping room takes over the tiles of a removed room
+        map_area.insert_room(Room::new(2, 10, 10, IVec2::new(15, 0), true));
+        assert!(map_area.point_to_room((16, 0)) == Some(1));
+        assert!(map_area.rooms_at((16, 0)) == vec![1, 2]);
+        assert!(map_area.rooms_at((12, 0)) == vec![1]);
+        assert!(map_area.rooms_at((30, 0)).is_empty());
+        map_area.remove_room(1);
+        assert!(map_area.rooms_at((16, 0)) == vec![2]);
+        assert!(map_area.tile_at(IVec2::new(16, 0)) == Some((2, UVec2::new(1, 0))));
+        assert!(map_area.point_to_room((12, 0)).is_none());
+
+        // replacing a room with a moved version of it
+        map_area.insert_room(Room::new(0, 10, 10, IVec2::new(-5, 0), true));
+        assert!(map_area.point_to_room((-5, 0)) == Some(0));
+        assert!(map_area.point_to_room((7, 0)).is_none());
+    }
+}
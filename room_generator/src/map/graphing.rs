@@ -4,7 +4,7 @@ use ordered_float::OrderedFloat;
 use petgraph::{algo::min_spanning_tree, data::FromElements, prelude::UnGraphMap};
 
 use super::{
-    generation::{MapArea, MapResource, WorldgenRng, WorldgenSettings},
+    generation::{MapArea, MapResource, WorldgenError, WorldgenSettings},
     room::{distance_between_structures, StructureDimensions},
 };
 use delaunator::{Point, Triangulation};
@@ -14,12 +14,14 @@ pub struct MyGraph(UnGraphMap<usize, f32>);
 
 #[derive(Debug, Clone)]
 pub struct RoomGraph {
+    pub delaunay: MyGraph, // the triangulation plus the initial connections from presets
     pub mst: MyGraph,
     pub reassembled_graph: MyGraph,
     pub main_path_rooms: Vec<usize>,
+    pub hub_rooms: Vec<usize>, // hubs at hallway bends and crossings. only known after `remake_graphs`
 }
 
-pub fn get_triangulation(mut map: ResMut<MapResource>) {
+pub fn get_triangulation(map: &mut MapResource) {
     let main_rooms = map.map_area_mut().get_main_rooms();
     let triangulation = triangulate(&main_rooms);
     //println!("{}", triangulation.len());
@@ -27,31 +29,28 @@ pub fn get_triangulation(mut map: ResMut<MapResource>) {
 }
 
 pub fn make_graphs(
-    mut map: ResMut<MapResource>,
-    worldgen: Res<WorldgenSettings>,
-    mut rng: ResMut<WorldgenRng>,
-) {
-    if map.map_area().triangulation.is_none() {
-        panic!("can't make graph with empty triangulation")
-    }
+    map: &mut MapResource,
+    worldgen: &WorldgenSettings,
+) -> Result<(), WorldgenError> {
+    let Some(triangulation) = map.map_area().triangulation.as_ref() else {
+        return Err(WorldgenError::MissingStageOutput("triangulation".into()));
+    };
 
     let rooms = map.map_area().get_main_rooms();
+    let room = |index: usize| {
+        rooms
+            .get(index)
+            .copied()
+            .ok_or_else(|| WorldgenError::MissingStageOutput(format!("main room {index}")))
+    };
     let mut graph = MyGraph::default();
 
     // if the triangulation has no elements, check the hull
     // (edge case with rooms on one axis, or with only 2 rooms)
-    if map.map_area().triangulation.as_ref().unwrap().is_empty() {
-        for (&a, &b) in map
-            .map_area()
-            .triangulation
-            .as_ref()
-            .unwrap()
-            .hull
-            .iter()
-            .tuple_windows()
-        {
-            let room1 = *rooms.get(a).unwrap();
-            let room2 = *rooms.get(b).unwrap();
+    if triangulation.is_empty() {
+        for (&a, &b) in triangulation.hull.iter().tuple_windows() {
+            let room1 = room(a)?;
+            let room2 = room(b)?;
             let distance1 = distance_between_structures(room1, room2);
             graph.add_edge(room1.id(), room2.id(), distance1);
         }
@@ -59,19 +58,11 @@ pub fn make_graphs(
 
     // if the triangulation is not empty...
     // for every triangle in the triangulation...
-    for (&a, &b, &c) in map
-        .map_area()
-        .triangulation
-        .as_ref()
-        .unwrap()
-        .triangles
-        .iter()
-        .tuples()
-    {
+    for (&a, &b, &c) in triangulation.triangles.iter().tuples() {
         // there are three rooms part of the triangulation
-        let room1 = *rooms.get(a).unwrap();
-        let room2 = *rooms.get(b).unwrap();
-        let room3 = *rooms.get(c).unwrap();
+        let room1 = room(a)?;
+        let room2 = room(b)?;
+        let room3 = room(c)?;
         let distance1 = distance_between_structures(room1, room2);
         let distance2 = distance_between_structures(room2, room3);
         let distance3 = distance_between_structures(room1, room3);
@@ -83,35 +74,56 @@ pub fn make_graphs(
 
     // add initial_connections
     for (a, b) in &map.map_area().initial_connections {
-        let room1 = *rooms.get(*a).unwrap();
-        let room2 = *rooms.get(*b).unwrap();
+        let room1 = room(*a)?;
+        let room2 = room(*b)?;
         let distance1 = distance_between_structures(room1, room2);
         graph.add_edge(room1.id(), room2.id(), distance1);
     }
 
+    // a single main room is the whole map, there is nothing to connect
+    if let [room] = rooms.as_slice() {
+        graph.add_node(room.id());
+        let room_graph = RoomGraph {
+            delaunay: graph.clone(),
+            mst: graph.clone(),
+            reassembled_graph: graph,
+            main_path_rooms: vec![room.id()],
+            hub_rooms: Vec::new(),
+        };
+        map.map_area_mut().graph = Some(room_graph);
+        return Ok(());
+    }
+    if graph.node_count() < 2 {
+        return Err(WorldgenError::EmptyTriangulation);
+    }
+
     // minimum spanning tree of the graph
     let mst = MyGraph(UnGraphMap::<_, _>::from_elements(min_spanning_tree(
         &graph.0,
     )));
 
-    let (start_room, end_room) = rooms_with_longest_distance_between_them(&mst, map.map_area());
-    let path_rooms = create_path_between_two_rooms(&mst, start_room, end_room);
+    let (start_room, end_room) = rooms_with_longest_distance_between_them(&mst, map.map_area())
+        .ok_or(WorldgenError::DisconnectedGraph)?;
+    let path_rooms = create_path_between_two_rooms(&mst, start_room, end_room)
+        .ok_or(WorldgenError::DisconnectedGraph)?;
 
     let room_graph = RoomGraph {
         mst: mst.clone(),
         reassembled_graph: reassemble_graph(
             &mst,
             &graph,
-            &map.map_area().triangulation.as_ref().unwrap(),
+            triangulation,
             Some(&path_rooms),
-            worldgen.graph_reassembly_percentage,
-            &mut rng,
-            &map.map_area()
+            worldgen,
+            &map.map_area(),
         ),
         main_path_rooms: path_rooms,
+        hub_rooms: Vec::new(),
+        delaunay: graph,
     };
 
     map.map_area_mut().graph = Some(room_graph);
+    Ok(())
 }
 
 /// creates a delaunay triangulation from all passed in structures
@@ -130,7 +142,10 @@ fn triangulate(rooms: &[&impl StructureDimensions]) -> Triangulation {
 /// this function takes a look at at all the main rooms which are edge rooms (only have one connection)
 /// and finds the two rooms that are the LONGEST distance apart from each other.
 /// better way: with pathfinding, but not implemented here
-fn rooms_with_longest_distance_between_them(mst: &MyGraph, map: &MapArea) -> (usize, usize) {
+fn rooms_with_longest_distance_between_them(
+    mst: &MyGraph,
+    map: &MapArea,
+) -> Option<(usize, usize)> {
     // edge_rooms are only the rooms which have ONE connection to another room
     let edge_rooms = map
         .get_main_rooms()
@@ -144,21 +159,25 @@ fn rooms_with_longest_distance_between_them(mst: &MyGraph, map: &MapArea) -> (us
         .cloned()
         .collect_vec();
 
-    // initialize with some random values that will never be reached.
     // type = (0: room1_id, 1: room2_id, 2: distance between r1 and r2)
-    let mut longest_distance_rooms: (usize, usize, f32) = (9999, 9999, -9999.);
+    // stays None if there are less than two edge rooms
+    let mut longest_distance_rooms: Option<(usize, usize, f32)> = None;
     for (&room1, &room2) in edge_rooms.iter().tuple_combinations() {
         let distance = distance_between_structures(room1, room2);
-        if distance > longest_distance_rooms.2 {
-            longest_distance_rooms = (room1.id(), room2.id(), distance);
+        if longest_distance_rooms.map_or(true, |(_, _, longest)| distance > longest) {
+            longest_distance_rooms = Some((room1.id(), room2.id(), distance));
         }
     }
-    (longest_distance_rooms.0, longest_distance_rooms.1)
+    longest_distance_rooms.map(|(room1, room2, _)| (room1, room2))
 }
 
 /// calculates the shortest path between start and end
-/// returns a list of room ids.
-fn create_path_between_two_rooms(graph: &MyGraph, start: usize, end: usize) -> Vec<usize> {
+/// returns a list of room ids, or None if start and end are not connected
+fn create_path_between_two_rooms(
+    graph: &MyGraph,
+    start: usize,
+    end: usize,
+) -> Option<Vec<usize>> {
     let path_rooms = pathfinding::prelude::dfs(
         start,
         |current| {
@@ -177,24 +196,24 @@ fn create_path_between_two_rooms(graph: &MyGraph, start: usize, end: usize) -> V
         },
         |current| *current == end,
     );
-    path_rooms.unwrap()
+    path_rooms
 }
 
 /// adds more edges into the graph
 /// this usecase: add edges from "all_connections" back into the minimum spanning tree graph
-/// ...to create cycles within the graph
+/// ...to create cycles (loops) within the graph
+///
+/// candidate edges are looked at from shortest to longest. an edge is only added if the loop it closes
+/// has between `min_loop_length` and `max_loop_length` rooms in it. this is repeated until `loops_amount` loops exist.
+/// no randomness is involved, so the same input graph always gives the same loops
 fn reassemble_graph(
     mst: &MyGraph,
     all_connections: &MyGraph,
     triangulation: &Triangulation,
     main_path: Option<&Vec<usize>>,
-    percentage: f32,
-    rng: &mut WorldgenRng,
-    map_area: &MapArea
+    worldgen: &WorldgenSettings,
+    map_area: &MapArea,
 ) -> MyGraph {
-    // make sure that percentage is between 0 and 1
-    let percentage = percentage.min(1.).max(0.);
-
     let mut output = mst.clone();
     let mut available_connections = all_connections.clone();
 
@@ -206,46 +225,222 @@ fn reassemble_graph(
     for (&a, &b) in triangulation.hull.iter().tuple_windows() {
         available_connections.remove_edge(a, b);
     }
-    // also do not consider main_path, if it exists
-    if let Some(main_path) = main_path {
-        for node in main_path.iter() {
-            available_connections.remove_node(*node);
-        }
-    }
     // also do not consider initial connections
     for (a, b) in map_area.initial_connections.iter() {
         available_connections.remove_edge(*a, *b);
     }
 
-    // add available connections back into graph
-    for edge in available_connections.0.all_edges() {
-        if rng.f32() <= percentage {
-            output.add_edge(edge.0, edge.1, edge.2.clone());
+    // sort by weight (and ids for equal weights), so the order never depends on the graph internals
+    let candidates = available_connections
+        .all_edges()
+        .map(|(a, b, weight)| (a.min(b), a.max(b), *weight))
+        .sorted_by(|x, y| {
+            OrderedFloat(x.2)
+                .cmp(&OrderedFloat(y.2))
+                .then(x.0.cmp(&y.0))
+                .then(x.1.cmp(&y.1))
+        })
+        .collect_vec();
+
+    let mut loops_added = 0;
+    for (a, b, weight) in candidates {
+        if loops_added >= worldgen.loops_amount {
+            break;
+        }
+        let Some(loop_rooms) = loop_closed_by_edge(&output, a, b) else {
+            continue;
+        };
+        if loop_rooms.len() < worldgen.min_loop_length
+            || loop_rooms.len() > worldgen.max_loop_length
+        {
+            continue;
         }
+        if !worldgen.loops_touch_main_path {
+            if let Some(main_path) = main_path {
+                if loop_rooms.iter().any(|room| main_path.contains(room)) {
+                    continue;
+                }
+            }
+        }
+        output.add_edge(a, b, weight);
+        loops_added += 1;
+    }
+
+    if loops_added < worldgen.loops_amount {
+        info!(
+            "only {} of {} loops could be added to the graph",
+            loops_added, worldgen.loops_amount
+        );
     }
 
     output
 }
 
+/// returns the rooms of the shortest loop that would be created by adding the edge a-b to the graph.
+/// if a and b are not connected in the graph, no loop would be created and None is returned
+fn loop_closed_by_edge(graph: &MyGraph, a: usize, b: usize) -> Option<Vec<usize>> {
+    if !graph.contains_node(a) || !graph.contains_node(b) {
+        return None;
+    }
+    pathfinding::prelude::bfs(
+        &a,
+        |current| graph.neighbors(*current).sorted().collect_vec(),
+        |current| *current == b,
+    )
+}
+
 /// this function remakes the connections between rooms
 /// after the rooms have been connected
 /// and only connects adjacent rooms via an edge
-pub fn remake_graphs(mut map: ResMut<MapResource>) {
+pub fn remake_graphs(map: &mut MapResource) -> Result<(), WorldgenError> {
     let mut graph = MyGraph::default();
 
-    for connection in map
+    let connections = map
         .map_area()
         .connections
         .as_ref()
-        .clone()
-        .expect("no connections for remake_graph were found")
-        .iter()
-    {
-        let room1 = map.map_area().rooms.get(&connection.room1_id).unwrap();
-        let room2 = map.map_area().rooms.get(&connection.room2_id).unwrap();
+        .ok_or_else(|| WorldgenError::MissingStageOutput("connections".into()))?;
+    for connection in connections.iter() {
+        let room = |id: usize| {
+            map.map_area()
+                .rooms
+                .get(&id)
+                .ok_or(WorldgenError::UnknownRoom(id))
+        };
+        let room1 = room(connection.room1_id)?;
+        let room2 = room(connection.room2_id)?;
         let distance = distance_between_structures(room1, room2);
         graph.add_edge(room1.id(), room2.id(), distance);
     }
+    // rooms of the main path are part of the graph even without connections, i.e. on a map with a single room
+    for &room_id in map.map_area().graph.iter().flat_map(|g| &g.main_path_rooms) {
+        graph.add_node(room_id);
+    }
+
+    let hub_rooms = map
+        .map_area()
+        .rooms
+        .values()
+        .filter(|r| r.is_hub())
+        .map(|r| r.id())
+        .sorted()
+        .collect_vec();
+
+    let room_graph = map
+        .map_area_mut()
+        .graph
+        .as_mut()
+        .ok_or_else(|| WorldgenError::MissingStageOutput("graph".into()))?;
+    room_graph.reassembled_graph = graph;
+    room_graph.hub_rooms = hub_rooms;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::room::Room;
+
+    fn chain_graph(length: usize) -> MyGraph {
+        let mut graph = MyGraph::default();
+        for i in 0..length - 1 {
+            graph.add_edge(i, i + 1, 1.);
+        }
+        graph
+    }
 
-    map.map_area_mut().graph.as_mut().unwrap().reassembled_graph = graph;
+    #[test]
+    fn test_main_path_of_broken_graphs() {
+        // two rooms that are not connected
+        let mut graph = chain_graph(3);
+        graph.add_node(7);
+        assert!(create_path_between_two_rooms(&graph, 0, 2).unwrap() == vec![0, 1, 2]);
+        assert!(create_path_between_two_rooms(&graph, 0, 7).is_none());
+        // no rooms at all
+        assert!(rooms_with_longest_distance_between_them(&graph, &MapArea::default()).is_none());
+    }
+
+    #[test]
+    fn test_single_room_graph() {
+        let mut map = MapResource::default();
+        map.map_area_mut()
+            .rooms
+            .insert(3, Room::new(3, 10, 10, IVec2::ZERO, true));
+        map.map_area_mut().triangulation = Some(Triangulation {
+            triangles: Vec::new(),
+            halfedges: Vec::new(),
+            hull: vec![0],
+        });
+        make_graphs(&mut map, &WorldgenSettings::default()).unwrap();
+        let room_graph = map.map_area().graph.as_ref().unwrap();
+        assert!(room_graph.main_path_rooms == vec![3]);
+        assert!(room_graph.reassembled_graph.node_count() == 1);
+
+        // without any room there is still nothing to make a graph from
+        map.map_area_mut().triangulation = Some(Triangulation {
+            triangles: Vec::new(),
+            halfedges: Vec::new(),
+            hull: Vec::new(),
+        });
+        assert!(
+            make_graphs(&mut map, &WorldgenSettings::default())
+                == Err(WorldgenError::EmptyTriangulation)
+        );
+    }
+
+    #[test]
+    fn test_loop_closed_by_edge() {
+        let graph = chain_graph(5);
+        assert!(loop_closed_by_edge(&graph, 0, 4).unwrap().len() == 5);
+        assert!(loop_closed_by_edge(&graph, 1, 3).unwrap() == vec![1, 2, 3]);
+        assert!(loop_closed_by_edge(&graph, 0, 10).is_none());
+    }
+
+    #[test]
+    fn test_reassemble_graph_loop_amount_and_length() {
+        // 0-1-2-3-4-5, candidates: 0-2 (loop of 3), 0-5 (loop of 6), 1-4 (loop of 4)
+        let mst = chain_graph(6);
+        let mut all_connections = mst.clone();
+        all_connections.add_edge(0, 2, 2.);
+        all_connections.add_edge(0, 5, 3.);
+        all_connections.add_edge(1, 4, 4.);
+        let triangulation = Triangulation {
+            triangles: Vec::new(),
+            halfedges: Vec::new(),
+            hull: Vec::new(),
+        };
+        let map_area = MapArea::default();
+
+        let mut worldgen = WorldgenSettings::default();
+        worldgen.loops_amount = 1;
+        worldgen.min_loop_length = 4;
+        worldgen.max_loop_length = 5;
+        worldgen.loops_touch_main_path = true;
+        let output =
+            reassemble_graph(&mst, &all_connections, &triangulation, None, &worldgen, &map_area);
+        assert!(output.edge_count() == mst.edge_count() + 1);
+        assert!(output.contains_edge(1, 4));
+
+        worldgen.loops_amount = 10;
+        worldgen.min_loop_length = 3;
+        worldgen.max_loop_length = 6;
+        let output =
+            reassemble_graph(&mst, &all_connections, &triangulation, None, &worldgen, &map_area);
+        assert!(output.edge_count() == mst.edge_count() + 3);
+
+        // the loop 1-2-3-4 touches room 3 of the main path
+        worldgen.loops_touch_main_path = false;
+        worldgen.min_loop_length = 4;
+        worldgen.max_loop_length = 4;
+        let main_path = vec![3];
+        let output = reassemble_graph(
+            &mst,
+            &all_connections,
+            &triangulation,
+            Some(&main_path),
+            &worldgen,
+            &map_area,
+        );
+        assert!(output.edge_count() == mst.edge_count());
+    }
 }
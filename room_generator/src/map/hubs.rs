@@ -0,0 +1,280 @@
+use bevy::prelude::*;
+use itertools::Itertools;
+
+use super::{
+    connecting::{are_two_rooms_adjacent, refresh_connections, RoomConnection},
+    generation::{MapArea, MapResource, WorldgenError, WorldgenSettings},
+    presets::RoomPresetResource,
+    room::{is_overlapping, Room, RoomDimensions, RoomType, StructureDimensions},
+};
+
+/// the side of a room another room is touching
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Left,
+    Right,
+    Down,
+    Up,
+}
+impl Side {
+    fn is_horizontal(&self) -> bool {
+        matches!(self, Side::Left | Side::Right)
+    }
+}
+
+/// turns bends and crossings of hallways into small hub rooms.
+/// a hub grows out of the hallway segment it replaces, the hallway segments next to it are made shorter to make room for it.
+/// hubs that would overlap other rooms are not placed
+pub fn place_hub_rooms(
+    map: &mut MapResource,
+    worldgen: &WorldgenSettings,
+    presets: &RoomPresetResource,
+) -> Result<(), WorldgenError> {
+    if !worldgen.hub_rooms {
+        return Ok(());
+    }
+    let mut connections = map
+        .map_area()
+        .connections
+        .clone()
+        .ok_or_else(|| WorldgenError::MissingStageOutput("connections".into()))?;
+
+    let candidates = map
+        .map_area()
+        .hallways()
+        .iter()
+        .filter(|h| is_bend_or_crossing(h, map.map_area(), &connections))
+        .map(|h| h.id())
+        .collect_vec();
+
+    for hub_id in candidates {
+        let Some(changed_rooms) = grow_hub(hub_id, worldgen, map.map_area(), &connections) else {
+            continue;
+        };
+        let changed_ids = changed_rooms.iter().map(|r| r.id()).collect_vec();
+        for room in changed_rooms {
+            map.map_area_mut().insert_room(room);
+        }
+        let hub = map.map_area_mut().rooms.get_mut(&hub_id).unwrap();
+        if let RoomType::Hallway(details) = &hub.details.room_type {
+            hub.details.room_type = RoomType::Hub(details.clone());
+        }
+        hub.details.aesthetic_modifiers = presets.hub_aesthetics().to_vec();
+
+        refresh_connections(&changed_ids, map.map_area(), &mut connections, worldgen);
+    }
+
+    map.map_area_mut().connections = Some(connections);
+    Ok(())
+}
+
+/// a hallway segment is a crossing if three or more rooms are connected to it,
+/// and a bend if it has two neighbours on sides that are not opposite of each other
+fn is_bend_or_crossing(hallway: &Room, map_area: &MapArea, connections: &[RoomConnection]) -> bool {
+    let sides = neighbours(hallway.id(), map_area, connections)
+        .iter()
+        .filter_map(|&neighbour| side_of(hallway, &map_area.rooms[&neighbour]))
+        .collect_vec();
+    match sides.len() {
+        2 => sides[0].is_horizontal() != sides[1].is_horizontal(),
+        n => n >= 3,
+    }
+}
+
+/// makes the hallway segment `hub_id` as big as a hub and shortens the hallway segments next to it.
+/// returns the changed rooms, or None if the hub does not fit
+fn grow_hub(
+    hub_id: usize,
+    worldgen: &WorldgenSettings,
+    map_area: &MapArea,
+    connections: &[RoomConnection],
+) -> Option<Vec<Room>> {
+    let hub = &map_area.rooms[&hub_id];
+    let neighbours = neighbours(hub_id, map_area, connections)
+        .into_iter()
+        .filter_map(|id| Some((id, side_of(hub, &map_area.rooms[&id])?)))
+        .collect_vec();
+
+    // real rooms cannot be made shorter, so the hub can only grow away from them
+    let blocked = |side: Side| {
+        neighbours
+            .iter()
+            .any(|&(id, s)| s == side && !map_area.rooms[&id].is_hallway())
+    };
+    let size = worldgen.hub_size as usize;
+    let (left, right) = split_growth(
+        size.saturating_sub(hub.length()),
+        blocked(Side::Left),
+        blocked(Side::Right),
+    );
+    let (down, up) = split_growth(
+        size.saturating_sub(hub.height()),
+        blocked(Side::Down),
+        blocked(Side::Up),
+    );
+    let grown = RoomDimensions {
+        anchor: hub.anchor_grid() - IVec2::new(left as i32, down as i32),
+        length: hub.length() + left + right,
+        height: hub.height() + down + up,
+    };
+
+    let mut changed_rooms = vec![Room::new2(hub_id, grown, hub.details.clone())];
+    for &(id, side) in neighbours.iter() {
+        let neighbour = &map_area.rooms[&id];
+        if !neighbour.is_hallway() {
+            continue;
+        }
+        let mut dimensions = RoomDimensions::from_dyn_structure_dim(neighbour);
+        match side {
+            Side::Left => dimensions.length = dimensions.length.checked_sub(left)?,
+            Side::Right => {
+                dimensions.length = dimensions.length.checked_sub(right)?;
+                dimensions.anchor.x += right as i32;
+            }
+            Side::Down => dimensions.height = dimensions.height.checked_sub(down)?,
+            Side::Up => {
+                dimensions.height = dimensions.height.checked_sub(up)?;
+                dimensions.anchor.y += up as i32;
+            }
+        }
+        if dimensions.length == 0 || dimensions.height == 0 {
+            return None;
+        }
+        changed_rooms.push(Room::new2(id, dimensions, neighbour.details.clone()));
+    }
+
+    // nothing else may be in the way of the hub
+    let changed_ids = changed_rooms.iter().map(|r| r.id()).collect_vec();
+    if map_area
+        .rooms
+        .values()
+        .filter(|r| !changed_ids.contains(&r.id()))
+        .chain(changed_rooms.iter().skip(1))
+        .any(|r| is_overlapping(&grown, r))
+    {
+        return None;
+    }
+
+    // every changed room still has to touch the rooms it was touching before
+    let current = |id: usize| {
+        changed_rooms
+            .iter()
+            .find(|r| r.id() == id)
+            .unwrap_or(&map_area.rooms[&id])
+    };
+    let still_adjacent = connections
+        .iter()
+        .filter(|c| c.is_adjacent())
+        .filter(|c| changed_ids.contains(&c.room1_id) || changed_ids.contains(&c.room2_id))
+        .all(|c| {
+            are_two_rooms_adjacent(
+                current(c.room1_id),
+                current(c.room2_id),
+                worldgen.min_passage_width,
+            )
+            .is_some()
+        });
+    still_adjacent.then_some(changed_rooms)
+}
+
+/// splits the growth of a hub between the two sides of an axis
+fn split_growth(growth: usize, first_blocked: bool, second_blocked: bool) -> (usize, usize) {
+    match (first_blocked, second_blocked) {
+        (true, true) => (0, 0),
+        (true, false) => (0, growth),
+        (false, true) => (growth, 0),
+        (false, false) => (growth / 2, growth - growth / 2),
+    }
+}
+
+/// ids of all rooms that have an adjacent connection to this room
+fn neighbours(room_id: usize, map_area: &MapArea, connections: &[RoomConnection]) -> Vec<usize> {
+    connections
+        .iter()
+        .filter(|c| c.is_adjacent())
+        .filter_map(|c| {
+            if c.room1_id == room_id {
+                Some(c.room2_id)
+            } else if c.room2_id == room_id {
+                Some(c.room1_id)
+            } else {
+                None
+            }
+        })
+        .filter(|id| map_area.rooms.contains_key(id))
+        .sorted()
+        .dedup()
+        .collect()
+}
+
+fn side_of(room: &impl StructureDimensions, other: &impl StructureDimensions) -> Option<Side> {
+    if other.anchor_grid_end().x == room.anchor_grid().x {
+        Some(Side::Left)
+    } else if other.anchor_grid().x == room.anchor_grid_end().x {
+        Some(Side::Right)
+    } else if other.anchor_grid_end().y == room.anchor_grid().y {
+        Some(Side::Down)
+    } else if other.anchor_grid().y == room.anchor_grid_end().y {
+        Some(Side::Up)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{connecting::RoomConnectionType, room::HallwayDetails};
+
+    #[test]
+    fn test_grow_hub_at_bend() {
+        let worldgen = WorldgenSettings {
+            hub_size: 10,
+            ..default()
+        };
+        let hallway = |id: usize, anchor: IVec2, length: usize, height: usize| {
+            let mut room = Room::new(id, length, height, anchor, false);
+            room.details.room_type = RoomType::Hallway(HallwayDetails::new((0, 1), id - 2, 6));
+            room
+        };
+        // [0]==[2]
+        //      ||
+        //      [3]
+        //      ||
+        //      [1]
+        let rooms = [
+            Room::new(0, 10, 6, IVec2::new(0, 40), true),
+            Room::new(1, 20, 10, IVec2::new(20, 0), true),
+            hallway(2, IVec2::new(10, 40), 20, 6),
+            hallway(3, IVec2::new(30, 40), 6, 6),
+            hallway(4, IVec2::new(30, 10), 6, 30),
+        ];
+        let mut map_area = MapResource::default().map_area().clone();
+        for room in rooms {
+            map_area.rooms.insert(room.id(), room);
+        }
+        let connections = [(0, 2), (2, 3), (3, 4), (4, 1)]
+            .into_iter()
+            .map(|(a, b)| {
+                let room1 = &map_area.rooms[&a];
+                let room2 = &map_area.rooms[&b];
+                let overlap = are_two_rooms_adjacent(room1, room2, 0);
+                assert!(overlap.is_some());
+                // the exact tiles do not matter for this test
+                RoomConnection::new(a, b, RoomConnectionType::Adjacent(Default::default()))
+            })
+            .collect_vec();
+
+        assert!(is_bend_or_crossing(&map_area.rooms[&3], &map_area, &connections));
+        assert!(!is_bend_or_crossing(&map_area.rooms[&4], &map_area, &connections));
+
+        let changed_rooms = grow_hub(3, &worldgen, &map_area, &connections).unwrap();
+        let hub = &changed_rooms[0];
+        assert!(hub.id() == 3);
+        assert!(hub.length() == 10 && hub.height() == 10);
+        for room in changed_rooms.iter().skip(1) {
+            assert!(!is_overlapping(hub, room));
+            assert!(are_two_rooms_adjacent(hub, room, 0).is_some());
+        }
+    }
+}
@@ -1,9 +1,15 @@
+use std::sync::Arc;
+
 use bevy::prelude::*;
 
 use crate::GameState;
 
 use self::{
-    generation::{MapResource, RegenerateRoomsEvent, WorldgenRng, WorldgenSettings},
+    generation::{
+        GenerationReport, MapResource, RegenerateRoomsEvent, WorldgenRng, WorldgenSettings,
+    },
+    pipeline::{PostprocessPass, PostprocessPipeline, PostprocessStep},
+    stages::{CustomStages, GenerationStage, StagePosition},
     visuals::WorldgenGizmos,
 };
 
@@ -11,62 +17,90 @@ pub mod room;
 pub mod util;
 pub mod aesthetics;
 pub mod connecting;
+pub mod doors;
+pub mod export;
 pub mod generation;
 pub mod graphing;
+pub mod hubs;
+pub mod pipeline;
 pub mod postprocess;
 pub mod separation;
+pub mod stages;
 pub mod visuals;
+pub mod wfc;
 pub mod presets;
+pub mod progression;
+pub mod world_tiles;
+
+/// custom generation stages are added with `with_stage_before` and `with_stage_after`, e.g.
+/// `MapPlugin::default().with_stage_after("carve_doors", SpawnTreasure)`.
+/// the names of the built-in stages are in stages.rs, every postprocess pass is a stage too.
+/// stages placed around a name that does not run are skipped with a warning
+#[derive(Default)]
+pub struct MapPlugin {
+    custom_stages: Vec<(StagePosition, Arc<dyn GenerationStage>)>,
+}
+impl MapPlugin {
+    /// runs `stage` right before the stage called `name`
+    pub fn with_stage_before(mut self, name: &str, stage: impl GenerationStage) -> Self {
+        self.custom_stages
+            .push((StagePosition::Before(name.into()), Arc::new(stage)));
+        self
+    }
 
-pub struct MapPlugin;
+    /// runs `stage` right after the stage called `name`
+    pub fn with_stage_after(mut self, name: &str, stage: impl GenerationStage) -> Self {
+        self.custom_stages
+            .push((StagePosition::After(name.into()), Arc::new(stage)));
+        self
+    }
+}
 impl Plugin for MapPlugin {
     fn build(&self, app: &mut App) {
         app.register_type::<WorldgenSettings>();
         app.register_type::<WorldgenGizmos>();
+        app.register_type::<doors::Door>();
+        app.register_type::<doors::DoorState>();
         app.init_resource::<WorldgenGizmos>();
         app.init_resource::<WorldgenSettings>();
         app.init_resource::<MapResource>();
+        app.register_type::<PostprocessPipeline>();
+        app.register_type::<PostprocessPass>();
+        app.register_type::<PostprocessStep>();
+        app.register_type::<Vec<PostprocessPass>>();
+        app.init_resource::<PostprocessPipeline>();
+        app.register_type::<GenerationReport>();
+        app.init_resource::<GenerationReport>();
+        app.insert_resource(CustomStages(self.custom_stages.clone()));
         app.add_event::<RegenerateRoomsEvent>();
         //app.add_systems(OnEnter(GameState::Playing), setup);
 
+        app.add_systems(Startup, pipeline::init_postprocess_pipeline);
         app.add_systems(PostStartup, init_worldgen_rng);
 
+        // the generation stages run one after another, see stages.rs. a failed stage returns a WorldgenError,
+        // which is stored by `handle_worldgen_error`. the remaining stages are skipped and the previous map stays on screen
         app.add_systems(
             PreUpdate,
             (
-                generation::despawn_chunks,
+                generation::clear_generation_report,
+                // init
+                self::init_worldgen_rng,
+                presets::init_preset_resource,
+                // generation, connecting, progression and postprocessing
+                stages::run_generation_stages.pipe(generation::handle_worldgen_error),
                 (
-                    // init
-                    self::init_worldgen_rng,
-                    presets::init_preset_resource,
-                    // generation with presets
-                    generation::generate_rooms,
-                    //generation::custom_rooms,
-                    generation::determine_main_rooms,
-                    // separation
-                    separation::separate_rooms,
-                    // graphing
-                    graphing::get_triangulation,
-                    graphing::make_graphs,
-                    //graphing::assign_meaning,
-                    //generation::replace_rooms,
-                    //separation::separate_rooms,
-                    // making connections between rooms
-                    connecting::connect_rooms,
-                    graphing::remake_graphs,
-                    //postprocessing
-                    postprocess::strip_unconnected_rooms,
-                    postprocess::outer_walls,
-                    postprocess::aesthetizise,
-                    postprocess::carve_path,
-                    postprocess::outer_walls, // do it again just to be sure
-                    postprocess::carve_doors,
-                    //postprocess::remove_random_walls,
+                    // only replace the old map if the new one is complete
+                    generation::despawn_chunks,
                     // spawn rooms into the world
                     visuals::spawn_rooms_visuals,
+                    doors::spawn_doors,
                 )
-                    .chain(),
+                    .chain()
+                    .distributive_run_if(generation::generation_ok),
+                generation::restore_previous_map.run_if(not(generation::generation_ok)),
             )
+                .chain()
                 .run_if(on_event::<RegenerateRoomsEvent>()),
         );
 
@@ -74,6 +108,8 @@ impl Plugin for MapPlugin {
             PostUpdate,
             check_for_worldgen_changes.run_if(in_state(GameState::Playing)),
         );
+        app.add_systems(Update, visuals::show_generation_error);
+        app.add_systems(Update, doors::update_door_sprites);
 
         app.add_systems(
             PostUpdate,
@@ -81,6 +117,8 @@ impl Plugin for MapPlugin {
                 visuals::gizmo_triangulation,
                 visuals::gizmo_room_middle_circle,
                 visuals::gizmo_graph_edges,
+                visuals::gizmo_hallways,
+                visuals::gizmo_doors,
             )
                 .chain(),
         );
@@ -89,9 +127,10 @@ impl Plugin for MapPlugin {
 
 fn check_for_worldgen_changes(
     worldgen: Res<WorldgenSettings>,
+    pipeline: Res<PostprocessPipeline>,
     mut events_w: EventWriter<RegenerateRoomsEvent>,
 ) {
-    if worldgen.is_changed() {
+    if worldgen.is_changed() || pipeline.is_changed() {
         events_w.send(RegenerateRoomsEvent);
     }
 }
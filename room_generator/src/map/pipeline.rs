@@ -0,0 +1,183 @@
+use std::{fs, sync::Arc};
+
+use bevy::prelude::*;
+
+use super::{
+    generation::WorldgenError,
+    postprocess,
+    stages::{GenerationStage, StageContext},
+};
+
+/// the postprocessing passes, in the order they run.
+/// loaded from worldgen/postprocess.ron at startup, passes can be turned off and changed in the inspector
+#[derive(Debug, Clone, Resource, Reflect, serde::Deserialize)]
+#[reflect(Resource)]
+pub struct PostprocessPipeline {
+    pub passes: Vec<PostprocessPass>,
+}
+
+#[derive(Debug, Clone, Reflect, serde::Deserialize)]
+pub struct PostprocessPass {
+    pub enabled: bool,
+    pub step: PostprocessStep,
+}
+
+#[derive(Debug, Clone, Reflect, serde::Deserialize)]
+pub enum PostprocessStep {
+    StripUnconnectedRooms,
+    OuterWalls,
+    Aesthetics {
+        destructive: bool, // aesthetics may overwrite the walls of the room
+    },
+    CarvePath,
+    Cleanup {
+        min_wall_cluster: usize, // smaller wall clusters inside of rooms are removed. 0 disables it
+        min_ground_region: usize, // smaller unreachable pockets are filled with walls, bigger ones are connected
+    },
+    CarveDoors,
+    DoorAesthetics {
+        destructive: bool, // the aesthetics that run after the doors are carved, see `Aesthetics::runs_after_doors`
+    },
+    VerifyConnectivity,
+}
+
+impl Default for PostprocessPipeline {
+    fn default() -> Self {
+        let passes = [
+            PostprocessStep::StripUnconnectedRooms,
+            PostprocessStep::OuterWalls,
+            PostprocessStep::Aesthetics { destructive: false },
+            PostprocessStep::CarvePath,
+            PostprocessStep::CarveDoors,
+            PostprocessStep::DoorAesthetics { destructive: false },
+            // after the doors, otherwise every room is still sealed by its outer walls and counts as a pocket
+            PostprocessStep::Cleanup {
+                min_wall_cluster: 3,
+                min_ground_region: 12,
+            },
+            PostprocessStep::VerifyConnectivity,
+        ];
+        Self {
+            passes: passes
+                .into_iter()
+                .map(|step| PostprocessPass {
+                    enabled: true,
+                    step,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// reads the pipeline from disk. the default pipeline is used if the file cannot be read
+pub fn init_postprocess_pipeline(mut commands: Commands) {
+    let path = "room_generator/assets/worldgen/postprocess.ron";
+    let pipeline = match fs::read_to_string(path) {
+        Ok(contents) => {
+            ron::de::from_str::<PostprocessPipeline>(&contents).unwrap_or_else(|error| {
+                warn!(
+                    "could not parse {}, using the default pipeline. log: {:?}",
+                    path, error
+                );
+                PostprocessPipeline::default()
+            })
+        }
+        Err(error) => {
+            warn!(
+                "could not read {}, using the default pipeline. log: {:?}",
+                path, error
+            );
+            PostprocessPipeline::default()
+        }
+    };
+    commands.insert_resource(pipeline);
+}
+
+impl PostprocessPipeline {
+    /// the enabled passes in order, each pass runs as its own generation stage
+    pub fn stages(&self) -> impl Iterator<Item = Arc<dyn GenerationStage>> + '_ {
+        self.passes
+            .iter()
+            .filter(|p| p.enabled)
+            .map(|p| Arc::new(p.step.clone()) as Arc<dyn GenerationStage>)
+    }
+}
+
+impl GenerationStage for PostprocessStep {
+    fn name(&self) -> &str {
+        match self {
+            PostprocessStep::StripUnconnectedRooms => "strip_unconnected_rooms",
+            PostprocessStep::OuterWalls => "outer_walls",
+            PostprocessStep::Aesthetics { .. } => "aesthetics",
+            PostprocessStep::CarvePath => "carve_path",
+            PostprocessStep::Cleanup { .. } => "cleanup",
+            PostprocessStep::CarveDoors => "carve_doors",
+            PostprocessStep::DoorAesthetics { .. } => "door_aesthetics",
+            PostprocessStep::VerifyConnectivity => "verify_connectivity",
+        }
+    }
+
+    fn run(&self, context: &mut StageContext) -> Result<(), WorldgenError> {
+        let map = &mut *context.map;
+        // the passages are as wide as the narrowest hallway, the plain doors as the widest
+        let passage_width = context.worldgen.min_passage_width;
+        let wide_width = context.worldgen.max_passage_width.max(passage_width);
+        match *self {
+            PostprocessStep::StripUnconnectedRooms => postprocess::strip_unconnected_rooms(map)?,
+            PostprocessStep::OuterWalls => postprocess::outer_walls(map),
+            PostprocessStep::Aesthetics { destructive } => postprocess::aesthetizise(
+                map,
+                context.rng,
+                destructive,
+                false,
+                passage_width,
+                wide_width,
+            )?,
+            // the doors open the outer walls later, so the paths keep them
+            PostprocessStep::CarvePath => postprocess::carve_path(map, passage_width, true)?,
+            PostprocessStep::Cleanup {
+                min_wall_cluster,
+                min_ground_region,
+            } => postprocess::remove_random_walls(
+                map,
+                min_wall_cluster,
+                min_ground_region,
+                passage_width,
+            )?,
+            PostprocessStep::CarveDoors => {
+                postprocess::carve_doors(map, passage_width, wide_width)?
+            }
+            PostprocessStep::DoorAesthetics { destructive } => postprocess::aesthetizise(
+                map,
+                context.rng,
+                destructive,
+                true,
+                passage_width,
+                wide_width,
+            )?,
+            PostprocessStep::VerifyConnectivity => {
+                postprocess::verify_connectivity(map, passage_width, context.report)?
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pipeline_file_matches_default() {
+        let pipeline = ron::de::from_str::<PostprocessPipeline>(include_str!(
+            "../../assets/worldgen/postprocess.ron"
+        ))
+        .unwrap();
+        let default = PostprocessPipeline::default();
+        assert!(pipeline.passes.len() == default.passes.len());
+        for (a, b) in pipeline.passes.iter().zip(default.passes.iter()) {
+            assert!(a.enabled == b.enabled);
+            assert!(format!("{:?}", a.step) == format!("{:?}", b.step));
+        }
+    }
+}
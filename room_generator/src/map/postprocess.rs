@@ -1,20 +1,33 @@
-use bevy::{prelude::*, utils::HashSet};
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
 use itertools::Itertools;
 
-use crate::map::room::Room;
+use crate::map::room::{DoorOrientation, Room, RoomShape, Tile};
 
 use super::{
-    connecting::RoomConnectionType,
-    generation::{MapResource, WorldgenSettings},
+    connecting::{ConnectionAttribute, RoomConnection, RoomConnectionType},
+    doors::Door,
+    generation::{GenerationReport, MapArea, MapResource, WorldgenError, WorldgenRng},
+    progression,
     room::StructureDimensions,
+    util::{common_edge, edge_key, find_out_door_orientation},
 };
 
-pub fn strip_unconnected_rooms(mut map: ResMut<MapResource>, worldgen: Res<WorldgenSettings>) {
-    if !worldgen.clear_unconnected_rooms {
-        return;
-    }
-    let mut rooms_with_connections = HashSet::new();
-    for connection in map.map_area().connections.as_ref().unwrap() {
+// the passes in this file are run by `pipeline::run_postprocess_pipeline`, in the order of the pipeline
+
+/// hides every room without a connection. the rooms of the main path always stay, a map can consist of a single room
+pub fn strip_unconnected_rooms(map: &mut MapResource) -> Result<(), WorldgenError> {
+    let mut rooms_with_connections = map
+        .map_area()
+        .graph
+        .iter()
+        .flat_map(|g| g.main_path_rooms.iter().copied())
+        .collect::<HashSet<_>>();
+    for connection in map.map_area().connections.as_ref().ok_or_else(|| {
+        WorldgenError::MissingStageOutput("connections".into())
+    })? {
         rooms_with_connections.insert(connection.room1_id);
         rooms_with_connections.insert(connection.room2_id);
     }
@@ -24,98 +37,221 @@ pub fn strip_unconnected_rooms(mut map: ResMut<MapResource>, worldgen: Res<World
             r.is_visible = false;
         }
     });
+    Ok(())
 }
 
-pub fn outer_walls(mut map: ResMut<MapResource>) {
+pub fn outer_walls(map: &mut MapResource) {
     for room in map.map_area_mut().rooms.values_mut() {
         room.fill_edges();
     }
 }
 
-/// some adjacent tiles are made into Ground tiles
-pub fn carve_doors(mut map: ResMut<MapResource>, worldgen: Res<WorldgenSettings>) {
+/// some adjacent tiles are made into Door tiles, and every carved door is stored in `MapArea::doors`
+///
+/// secret connections are not carved at all, so they look like walls.
+/// locked doors and boss gates are carved with the narrow width, everything else with the wide width.
+/// one-way connections are drops, the side of room1 is carved as a ledge instead of a door
+pub fn carve_doors(
+    map: &mut MapResource,
+    narrow_width: u32,
+    wide_width: u32,
+) -> Result<(), WorldgenError> {
     let connections = map
         .map_area()
         .connections
-        .as_ref()
-        .expect("cannot carve doors without knowing about room connections")
-        .clone();
+        .clone()
+        .ok_or_else(|| WorldgenError::MissingStageOutput("connections".into()))?;
+    map.map_area_mut().doors.clear();
+    let mut footprint_changed = false;
     for c in connections.iter() {
-        match &c.data {
-            RoomConnectionType::Adjacent(adjacent_tiles) => {
-                let room = map.map_area_mut().rooms.get_mut(&c.room1_id).unwrap();
-                let mut tiles = adjacent_tiles.room1.1.clone();
-                remove_outer_tiles(&mut tiles);
-                cut(&mut tiles, &worldgen);
-                carve(room, &tiles);
+        let Some((tiles1, tiles2)) = door_tiles(map.map_area(), c, narrow_width, wide_width)?
+        else {
+            continue;
+        };
+        let (overlap_x, overlap_y) = common_edge(
+            room(map.map_area(), c.room1_id)?,
+            room(map.map_area(), c.room2_id)?,
+        );
+        let orientation = find_out_door_orientation(overlap_x, overlap_y);
+        let is_one_way = c.has_attribute(ConnectionAttribute::OneWay);
 
-                let room = map.map_area_mut().rooms.get_mut(&c.room2_id).unwrap();
-                let mut tiles = adjacent_tiles.room2.1.clone();
-                remove_outer_tiles(&mut tiles);
-                cut(&mut tiles, &worldgen);
-                carve(room, &tiles);
-            }
-            _ => (),
+        let tile = if is_one_way { Tile::Ledge } else { Tile::Door };
+        let (changed1, carved1) = carve(room_mut(map, c.room1_id)?, &tiles1, tile);
+        let (changed2, carved2) = carve(room_mut(map, c.room2_id)?, &tiles2, Tile::Door);
+        footprint_changed |= changed1 || changed2;
+
+        // both rooms carve the same amount of tiles, next to each other. the passages make the door deeper
+        let carved = carved1.into_iter().chain(carved2).collect_vec();
+        let min = carved.iter().copied().reduce(IVec2::min);
+        let max = carved.iter().copied().reduce(IVec2::max);
+        if let (Some(position), Some(max)) = (min, max) {
+            let depth = match orientation {
+                DoorOrientation::Vertical => max.x - position.x + 1,
+                DoorOrientation::Horziontal => max.y - position.y + 1,
+            };
+            map.map_area_mut().doors.push(Door {
+                position,
+                width: tiles1.len() as u32,
+                depth: depth as u32,
+                orientation,
+                rooms: (c.room1_id, c.room2_id),
+                is_locked: c.is_locked(),
+                is_one_way,
+                is_boss_gate: c.has_attribute(ConnectionAttribute::BossGate),
+            });
         }
     }
+    // the passages into shaped rooms are new tiles of those rooms
+    if footprint_changed {
+        map.map_area_mut().rebuild_tile_index();
+    }
 
-    fn cut<T>(tiles: &mut Vec<T>, worldgen: &WorldgenSettings) {
-        let l: i32 = tiles.len() as i32 - worldgen.max_passage_width as i32;
-        if  l > 0 {
-            for _ in 0..l {
-              tiles.pop();
-            }
-        } 
+    fn room_mut(map: &mut MapResource, id: usize) -> Result<&mut Room, WorldgenError> {
+        map.map_area_mut()
+            .rooms
+            .get_mut(&id)
+            .ok_or(WorldgenError::UnknownRoom(id))
     }
 
-    fn carve(room: &mut Room, tiles: &[UVec2]) {
+    /// returns true if passages were carved into a shaped room,
+    /// and every carved tile of the door and the passages in global coordinates
+    fn carve(room: &mut Room, tiles: &[UVec2], tile: Tile) -> (bool, Vec<IVec2>) {
+        let is_shaped = room.details.shape != RoomShape::Rect;
+        let mut carved = vec![];
         for &tile_position in tiles.iter() {
-            room.set_tile(tile_position, super::room::Tile::Ground);
+            if is_shaped {
+                carved.extend(carve_to_footprint(room, tile_position));
+            }
+            room.extend_footprint(tile_position, tile);
+            carved.push(tile_position);
         }
+        let carved = carved.into_iter().map(|t| room.local_to_global(t)).collect();
+        (is_shaped && !tiles.is_empty(), carved)
     }
-    fn remove_outer_tiles<T>(tiles: &mut Vec<T>) {
-        if tiles.len() >= 2 {
-            tiles.remove(0);
-            tiles.pop();
-        } else {
-            tiles.clear()
-        }
-    }
-}
-
-/// this function ENSURES that EVERY room can be entered, i.e. no walls blocking the entrance
-pub fn carve_path(mut map: ResMut<MapResource>, worldgen: Res<WorldgenSettings>) {
-    // huge mess
-    fn successors(current_tile: IVec2, map: &MapResource) -> Vec<(IVec2, u32)> {
-        let mut successors = vec![
-            current_tile + IVec2::new(1, 0),
-            current_tile + IVec2::new(0, 1),
-            current_tile + IVec2::new(-1, 0),
-            current_tile + IVec2::new(0, -1),
-        ];
-        // the points could not belong to any room, so we have to filter them. if the point is not in a room, remove it from successors
-        successors.retain(|&e| map.map_area().point_to_room((e.x, e.y)).is_some());
-
-        let successors: Vec<(IVec2, u32)> = successors
-            .iter()
-            .map(|e| {
-                let room = map
-                    .map_area()
-                    .rooms
-                    .get(&map.map_area().point_to_room((e.x, e.y)).unwrap())
-                    .unwrap();
-                let local_coordinates = room.global_to_local(*e).unwrap();
-                let cost = match room.get_tile(local_coordinates).unwrap() {
-                    crate::map::room::Tile::Ground => 1,
-                    crate::map::room::Tile::Wall => 100,
-                };
 
-                (*e, cost)
-            })
-            .collect_vec();
-        successors
+    Ok(())
+}
+
+fn room(map_area: &MapArea, id: usize) -> Result<&Room, WorldgenError> {
+    map_area.rooms.get(&id).ok_or(WorldgenError::UnknownRoom(id))
+}
+
+/// the tiles of both rooms that `carve_doors` turns into a door, in local coordinates of each room.
+/// secret connections and connections between rooms that do not touch have none
+fn door_tiles(
+    map_area: &MapArea,
+    c: &RoomConnection,
+    narrow_width: u32,
+    wide_width: u32,
+) -> Result<Option<(Vec<UVec2>, Vec<UVec2>)>, WorldgenError> {
+    if c.has_attribute(ConnectionAttribute::Secret) {
+        return Ok(None);
+    }
+    let RoomConnectionType::Adjacent(adjacent_tiles) = &c.data else {
+        return Ok(None);
+    };
+    let width = if c.is_locked() || c.has_attribute(ConnectionAttribute::BossGate) {
+        narrow_width
+    } else {
+        wide_width
+    };
+    let mut tiles1 = adjacent_tiles.room1.1.clone();
+    remove_outer_tiles(&mut tiles1);
+    let mut tiles2 = adjacent_tiles.room2.1.clone();
+    remove_outer_tiles(&mut tiles2);
+    let offset = door_offset(
+        room(map_area, c.room1_id)?,
+        &tiles1,
+        room(map_area, c.room2_id)?,
+        &tiles2,
+        width,
+    );
+    cut(&mut tiles1, offset, width);
+    cut(&mut tiles2, offset, width);
+    Ok(Some((tiles1, tiles2)))
+}
+fn cut<T>(tiles: &mut Vec<T>, offset: usize, width: u32) {
+    tiles.drain(..offset.min(tiles.len()));
+    tiles.truncate(width as usize);
+}
+fn remove_outer_tiles<T>(tiles: &mut Vec<T>) {
+    if tiles.len() >= 2 {
+        tiles.remove(0);
+        tiles.pop();
+    } else {
+        tiles.clear()
+    }
+}
+
+/// the first of the adjacent tiles that becomes a door. the door goes where the fewest of its tiles are outside of the
+/// footprints of the two rooms, so it attaches to the shape of the room. rectangular rooms always start at the first tile
+fn door_offset(
+    room1: &Room,
+    tiles1: &[UVec2],
+    room2: &Room,
+    tiles2: &[UVec2],
+    width: u32,
+) -> usize {
+    let is_void = |room: &Room, tile: UVec2| room.get_tile(tile) == Some(&Tile::Void);
+    let length = tiles1.len().min(tiles2.len());
+    let width = (width as usize).min(length);
+    (0..=length - width)
+        .min_by_key(|&offset| {
+            (offset..offset + width)
+                .filter(|&i| is_void(room1, tiles1[i]) || is_void(room2, tiles2[i]))
+                .count()
+        })
+        .unwrap_or(0)
+}
+
+/// the door of a shaped room can be outside of its footprint or behind a thick diagonal wall.
+/// carves a passage from the door into the room until it reaches the first tile inside of the outer walls,
+/// the Void tiles next to the passage become walls. returns the tiles of the passage, without the door
+fn carve_to_footprint(room: &mut Room, door: UVec2) -> Vec<UVec2> {
+    let door = door.as_ivec2();
+    let direction = if door.x == 0 {
+        IVec2::X
+    } else if door.x == room.length() as i32 - 1 {
+        IVec2::NEG_X
+    } else if door.y == 0 {
+        IVec2::Y
+    } else {
+        IVec2::NEG_Y
+    };
+    let floor = room.floor_tile();
+    let mut position = door;
+    let mut passage = vec![];
+    loop {
+        for side in [position + direction.perp(), position - direction.perp()] {
+            if room.get_tile(side.as_uvec2()) == Some(&Tile::Void) {
+                room.extend_footprint(side.as_uvec2(), Tile::Wall);
+            }
+        }
+        position += direction;
+        let Some(tile) = room.get_tile(position.as_uvec2()).copied() else {
+            break;
+        };
+        // a room full of walls is not tunneled through, the passage ends inside of the outer walls
+        let is_inside = tile != Tile::Void && !room.is_edge_tile(position.as_uvec2());
+        if !tile.is_safe() {
+            room.extend_footprint(position.as_uvec2(), floor);
+        }
+        if is_inside {
+            break;
+        }
+        passage.push(position.as_uvec2());
     }
+    passage
+}
 
+/// this function ENSURES that EVERY room can be entered, i.e. no walls blocking the entrance.
+/// runs a* between the middles of every two connected rooms of the graph and carves along the path.
+/// with `keep_outer_walls` the edges of the rooms are not carved, the doors open them later
+pub fn carve_path(
+    map: &mut MapResource,
+    passage_width: u32,
+    keep_outer_walls: bool,
+) -> Result<(), WorldgenError> {
     // (chebyshev metric) as seen in https://chris3606.github.io/GoRogue/articles/grid_components/measuring-distance.html
     // i.e.
     // 2 2 2 2 2
@@ -147,23 +283,29 @@ pub fn carve_path(mut map: ResMut<MapResource>, worldgen: Res<WorldgenSettings>)
         cost.x.max(cost.y).pow(3)
     } */
 
-    // get all edges of the graph
-    for (room1, room2, _weight) in map
+    let graph = map
         .map_area()
         .graph
-        .as_ref()
-        .unwrap()
         .clone()
-        .reassembled_graph
-        .all_edges()
-    {
-        let room1 = map.map_area().rooms.get(&room1).unwrap();
-        let room2 = map.map_area().rooms.get(&room2).unwrap();
+        .ok_or_else(|| WorldgenError::MissingStageOutput("graph".into()))?;
+
+    // get all edges of the graph
+    for (room1, room2, _weight) in graph.reassembled_graph.all_edges() {
+        let room1 = map
+            .map_area()
+            .rooms
+            .get(&room1)
+            .ok_or(WorldgenError::UnknownRoom(room1))?;
+        let room2 = map
+            .map_area()
+            .rooms
+            .get(&room2)
+            .ok_or(WorldgenError::UnknownRoom(room2))?;
 
         // run a* between every room
         let path = pathfinding::directed::astar::astar(
             &room1.center_grid().as_ivec2(),
-            |current_tile| successors(*current_tile, &map),
+            |current_tile| path_successors(*current_tile, map.map_area()),
             |h| {
                 let distance = h.distance_squared(room2.center_grid().as_ivec2()).abs() as u32;
                 //let inner_tile_preference = prefer_inner_tiles(*h, &map);
@@ -180,45 +322,387 @@ pub fn carve_path(mut map: ResMut<MapResource>, worldgen: Res<WorldgenSettings>)
                 room1.id(),
                 room2.id()
             );
-            return;
-        }
-
-        for tile_position in path.unwrap().0.iter() {
-            let point_to_room = map
-                .map_area()
-                .point_to_room((tile_position.x, tile_position.y))
-                .unwrap();
-            let room = map.map_area_mut().rooms.get_mut(&point_to_room).unwrap();
-            let local_coordinates = room.global_to_local(*tile_position).unwrap();
-
-            // carve the path
-            for x in local_coordinates
-                .x
-                .saturating_sub(worldgen.min_passage_width / 2)
-                ..=local_coordinates.x + worldgen.min_passage_width / 2
+            continue;
+        }
+
+        carve_along_path(map, &path.unwrap().0, passage_width, keep_outer_walls)?;
+    }
+    Ok(())
+}
+
+/// the four neighbours of a tile that are part of a visible room, with the cost of walking onto them
+fn path_successors(current_tile: IVec2, map_area: &MapArea) -> Vec<(IVec2, u32)> {
+    [
+        current_tile + IVec2::new(1, 0),
+        current_tile + IVec2::new(0, 1),
+        current_tile + IVec2::new(-1, 0),
+        current_tile + IVec2::new(0, -1),
+    ]
+    .into_iter()
+    // the points could not belong to any room, so we have to filter them. if the point is not in a room, remove it from successors
+    .filter_map(|e| {
+        let (room_id, local) = map_area.tile_at(e)?;
+        let room = &map_area.rooms[&room_id];
+        if !room.is_visible {
+            return None;
+        }
+        let cost = if room.get_tile(local)?.is_safe() {
+            1
+        } else {
+            100
+        };
+        Some((e, cost))
+    })
+    .collect_vec()
+}
+
+/// turns every tile around the path that cannot be walked on safely into the floor of its room
+fn carve_along_path(
+    map: &mut MapResource,
+    path: &[IVec2],
+    passage_width: u32,
+    keep_outer_walls: bool,
+) -> Result<(), WorldgenError> {
+    for tile_position in path.iter() {
+        // the middle of a shaped room can be outside of its footprint
+        let Some((room_id, local_coordinates)) = map.map_area().tile_at(*tile_position) else {
+            continue;
+        };
+        let room = map
+            .map_area_mut()
+            .rooms
+            .get_mut(&room_id)
+            .ok_or(WorldgenError::UnknownRoom(room_id))?;
+
+        // carve the path
+        for x in local_coordinates.x.saturating_sub(passage_width / 2)
+            ..=local_coordinates.x + passage_width / 2
+        {
+            for y in local_coordinates.y.saturating_sub(passage_width / 2)
+                ..=local_coordinates.y + passage_width / 2
             {
-                for y in local_coordinates
-                    .y
-                    .saturating_sub(worldgen.min_passage_width / 2)
-                    ..=local_coordinates.y + worldgen.min_passage_width / 2
-                {
-                    let position: UVec2 = UVec2::new(x, y);
-                    room.set_tile(position, crate::map::room::Tile::Ground);
+                let position: UVec2 = UVec2::new(x, y);
+                if keep_outer_walls && room.is_edge_tile(position) {
+                    continue;
                 }
+                if room.get_tile(position).is_some_and(|tile| !tile.is_safe()) {
+                    room.set_tile(position, room.floor_tile());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// flood fills walkable tiles from the start room and checks that every main room and every room on the main path can be reached.
+/// unreachable rooms get a path carved to the closest reachable tile. rooms that still cannot be reached are written into the GenerationReport.
+/// rooms behind secret, locked and one-way connections are meant to be out of reach, they are reported as gated and never carved open
+pub fn verify_connectivity(
+    map: &mut MapResource,
+    passage_width: u32,
+    report: &mut GenerationReport,
+) -> Result<(), WorldgenError> {
+    let Some(graph) = map.map_area().graph.clone() else {
+        return Err(WorldgenError::MissingStageOutput("graph".into()));
+    };
+    let Some(&start_room) = graph.main_path_rooms.first() else {
+        return Ok(());
+    };
+    let gated_rooms = gated_rooms(map.map_area(), start_room);
+    let (rooms_to_check, gated_rooms_to_check): (Vec<_>, Vec<_>) = map
+        .map_area()
+        .rooms
+        .values()
+        .filter(|r| r.is_visible && (r.details.is_main || graph.main_path_rooms.contains(&r.id())))
+        .map(|r| r.id())
+        .sorted()
+        .partition(|id| !gated_rooms.contains(id));
+    report.gated_rooms = gated_rooms_to_check;
+
+    let mut reachable = reachable_ground_tiles(map.map_area(), start_room, &gated_rooms);
+    for room_id in rooms_to_check {
+        if is_room_reached(map.map_area(), room_id, &reachable) {
+            continue;
+        }
+
+        // repair: carve from the middle of the room to the closest reachable tile
+        let center = map.map_area().rooms[&room_id].center_grid().as_ivec2();
+        // nothing runs after this pass that could close the outer walls again, so the path may go through them
+        if !carve_to_reachable(map, center, &reachable, passage_width, false, &gated_rooms)? {
+            report.unreachable_rooms.push(room_id);
+            continue;
+        }
+        reachable = reachable_ground_tiles(map.map_area(), start_room, &gated_rooms);
+        if is_room_reached(map.map_area(), room_id, &reachable) {
+            report.repaired_rooms.push(room_id);
+        } else {
+            report.unreachable_rooms.push(room_id);
+        }
+    }
+
+    if !report.unreachable_rooms.is_empty() {
+        warn!("unreachable rooms: {:?}", report.unreachable_rooms);
+    }
+    Ok(())
+}
+
+/// rooms that cannot be reached from the start room without going through a secret, locked or one-way connection
+fn gated_rooms(map_area: &MapArea, start_room: usize) -> HashSet<usize> {
+    let open_rooms = open_rooms(map_area, start_room);
+    map_area
+        .rooms
+        .keys()
+        .copied()
+        .filter(|id| !open_rooms.contains(id))
+        .collect()
+}
+
+/// rooms that can be reached from the start room without going through a secret, locked or one-way connection.
+/// the start room is always open, even if it has no connections
+fn open_rooms(map_area: &MapArea, start_room: usize) -> HashSet<usize> {
+    let Some(graph) = map_area.graph.as_ref() else {
+        return HashSet::new();
+    };
+    let barriers = map_area
+        .connections
+        .iter()
+        .flatten()
+        .filter(|c| {
+            c.is_locked()
+                || c.has_attribute(ConnectionAttribute::Secret)
+                || c.has_attribute(ConnectionAttribute::OneWay)
+        })
+        .map(|c| edge_key(c.room1_id, c.room2_id))
+        .collect::<HashSet<_>>();
+    let mut rooms = progression::reachable_rooms(&graph.reassembled_graph, start_room, |a, b| {
+        !barriers.contains(&edge_key(a, b))
+    });
+    rooms.insert(start_room);
+    rooms
+}
+
+/// carves a path from `from` to the closest reachable tile. returns false if there is no path.
+/// the path does not go through the tiles of `excluded_rooms`, and with `keep_outer_walls` not through the outer walls either
+fn carve_to_reachable(
+    map: &mut MapResource,
+    from: IVec2,
+    reachable: &HashSet<IVec2>,
+    passage_width: u32,
+    keep_outer_walls: bool,
+    excluded_rooms: &HashSet<usize>,
+) -> Result<bool, WorldgenError> {
+    let Some(&target) = reachable
+        .iter()
+        .min_by_key(|t| (t.distance_squared(from), t.x, t.y))
+    else {
+        return Ok(false);
+    };
+    let path = pathfinding::directed::astar::astar(
+        &from,
+        |current_tile| {
+            let mut successors = path_successors(*current_tile, map.map_area());
+            // the outer walls would not be carved, a path through them would not connect anything
+            successors.retain(|(tile, _)| {
+                !is_in_rooms(map.map_area(), *tile, excluded_rooms)
+                    && !(keep_outer_walls && is_outer_wall(map.map_area(), *tile))
+            });
+            successors
+        },
+        |h| h.distance_squared(target).abs() as u32,
+        |s| reachable.contains(s),
+    );
+    match path {
+        Some((path, _)) => {
+            carve_along_path(map, &path, passage_width, keep_outer_walls)?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// all walkable tiles (in global coordinates) that can be walked to from the start room
+/// without walking through the tiles of `excluded_rooms`
+fn reachable_ground_tiles(
+    map_area: &MapArea,
+    start_room: usize,
+    excluded_rooms: &HashSet<usize>,
+) -> HashSet<IVec2> {
+    let Some(room) = map_area.rooms.get(&start_room) else {
+        return HashSet::new();
+    };
+
+    // start at the ground tile that is closest to the middle of the room
+    let center = room.center_grid().as_ivec2();
+    let Some(start) = (0..room.height())
+        .flat_map(|y| (0..room.length()).map(move |x| UVec2::new(x as u32, y as u32)))
+        .map(|local| room.local_to_global(local))
+        .filter(|&tile| is_walkable(map_area, tile))
+        .min_by_key(|tile| tile.distance_squared(center))
+    else {
+        return HashSet::new();
+    };
+    walkable_region(map_area, start, excluded_rooms)
+}
+
+fn is_walkable(map_area: &MapArea, tile: IVec2) -> bool {
+    map_area.tile_at(tile).is_some_and(|(id, local)| {
+        let room = &map_area.rooms[&id];
+        room.is_visible && room.get_tile(local).is_some_and(|t| t.is_walkable())
+    })
+}
+
+/// an edge tile of its room that cannot be walked on safely, doors are not part of the outer walls
+fn is_outer_wall(map_area: &MapArea, tile: IVec2) -> bool {
+    map_area.tile_at(tile).is_some_and(|(id, local)| {
+        let room = &map_area.rooms[&id];
+        room.is_edge_tile(local) && !room.get_tile(local).is_some_and(|t| t.is_safe())
+    })
+}
+
+fn is_in_rooms(map_area: &MapArea, tile: IVec2, rooms: &HashSet<usize>) -> bool {
+    map_area
+        .tile_at(tile)
+        .is_some_and(|(id, _)| rooms.contains(&id))
+}
+
+/// flood fills walkable tiles from `start`, without going into `excluded_rooms`
+fn walkable_region(
+    map_area: &MapArea,
+    start: IVec2,
+    excluded_rooms: &HashSet<usize>,
+) -> HashSet<IVec2> {
+    let mut region = HashSet::new();
+    let mut stack = vec![start];
+    region.insert(start);
+    while let Some(current) = stack.pop() {
+        for direction in [IVec2::X, IVec2::Y, IVec2::NEG_X, IVec2::NEG_Y] {
+            let next = current + direction;
+            if !region.contains(&next)
+                && is_walkable(map_area, next)
+                && !is_in_rooms(map_area, next, excluded_rooms)
+            {
+                region.insert(next);
+                stack.push(next);
             }
         }
     }
+    region
+}
+
+/// a room is reached if a floor tile inside of its outer walls can be reached. a door alone can open into a walled off pocket
+fn is_room_reached(map_area: &MapArea, room_id: usize, reachable: &HashSet<IVec2>) -> bool {
+    let room = &map_area.rooms[&room_id];
+    (0..room.height())
+        .flat_map(|y| (0..room.length()).map(move |x| UVec2::new(x as u32, y as u32)))
+        .filter(|&local| {
+            !room.is_edge_tile(local) && room.get_tile(local).is_some_and(|tile| tile.is_floor())
+        })
+        .any(|local| reachable.contains(&room.local_to_global(local)))
 }
 
-pub fn aesthetizise(mut map: ResMut<MapResource>, _worldgen: Res<WorldgenSettings>) {
+/// runs the aesthetics of every room. with `after_doors` only the aesthetics that run after `carve_doors` are run
+/// (see `Aesthetics::runs_after_doors`), without it only the others.
+/// the aesthetics after the doors can wall them off, so afterwards a path is carved from every door to the middle of the room
+pub fn aesthetizise(
+    map: &mut MapResource,
+    rng: &mut WorldgenRng,
+    destructive: bool,
+    after_doors: bool,
+    narrow_width: u32,
+    wide_width: u32,
+) -> Result<(), WorldgenError> {
+    let doors = door_positions(map.map_area(), narrow_width, wide_width)?;
+    // one seed for every room, so the order of the rooms does not matter
+    let seed = rng.u64(..);
     for room in map.map_area_mut().rooms.values_mut() {
+        if room.is_hallway() && !after_doors {
+            room.replace_tiles(Tile::Ground, Tile::HallwayFloor);
+        }
+        let room_doors = doors.get(&room.id()).cloned().unwrap_or_default();
+        let room_seed = seed ^ (room.id() as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        let mut changed = false;
         for modifier in &room.details.aesthetic_modifiers.clone() {
-            modifier.generate_features(room, false);
+            if modifier.runs_after_doors() == after_doors {
+                modifier.generate_features(room, destructive, &room_doors, room_seed);
+                changed = true;
+            }
+        }
+        if after_doors && changed {
+            carve_doors_to_center(room, &room_doors, narrow_width);
+        }
+    }
+    Ok(())
+}
+
+/// runs a* from every door to the middle of the room and carves along the path. walls and hazards cost more than the floor,
+/// so the path only carves where there is no other way. the outer walls are never carved
+fn carve_doors_to_center(room: &mut Room, doors: &[UVec2], passage_width: u32) {
+    let center = IVec2::new(room.length() as i32 / 2, room.height() as i32 / 2);
+    for door in doors.iter() {
+        let path = pathfinding::directed::astar::astar(
+            &door.as_ivec2(),
+            |&tile| {
+                [IVec2::X, IVec2::Y, IVec2::NEG_X, IVec2::NEG_Y]
+                    .into_iter()
+                    .filter_map(|direction| {
+                        let next = tile + direction;
+                        let next_tile = *room.get_tile(next.as_uvec2())?;
+                        // the passages into shaped rooms are on the edge too, they can be walked through
+                        if next_tile == Tile::Void
+                            || (room.is_edge_tile(next.as_uvec2()) && !next_tile.is_safe())
+                        {
+                            return None;
+                        }
+                        let cost = if next_tile.is_safe() { 1 } else { 100 };
+                        Some((next, cost))
+                    })
+                    .collect_vec()
+            },
+            |tile| {
+                let distance = (center - *tile).abs();
+                (distance.x + distance.y) as u32
+            },
+            |tile| *tile == center,
+        );
+        let Some((path, _)) = path else {
+            continue;
+        };
+
+        for tile in path.iter() {
+            let tile = tile.as_uvec2();
+            for x in tile.x.saturating_sub(passage_width / 2)..=tile.x + passage_width / 2 {
+                for y in tile.y.saturating_sub(passage_width / 2)..=tile.y + passage_width / 2 {
+                    let position = UVec2::new(x, y);
+                    if !room.is_edge_tile(position)
+                        && room.get_tile(position).is_some_and(|t| !t.is_safe())
+                    {
+                        room.set_tile(position, room.floor_tile());
+                    }
+                }
+            }
         }
     }
 }
 
-/* /// an implementation of a flood fill algorithm to catch wall formations that are very tiny
+/// every tile of the doors that `carve_doors` is going to carve, in local coordinates of each room
+fn door_positions(
+    map_area: &MapArea,
+    narrow_width: u32,
+    wide_width: u32,
+) -> Result<HashMap<usize, Vec<UVec2>>, WorldgenError> {
+    let mut doors: HashMap<usize, Vec<UVec2>> = HashMap::new();
+    for c in map_area.connections.iter().flatten() {
+        let Some((tiles1, tiles2)) = door_tiles(map_area, c, narrow_width, wide_width)? else {
+            continue;
+        };
+        for (room_id, tiles) in [(c.room1_id, tiles1), (c.room2_id, tiles2)] {
+            doors.entry(room_id).or_default().extend(tiles);
+        }
+    }
+    Ok(doors)
+}
+
+/// an implementation of a flood fill algorithm to catch wall formations that are very tiny
 /// those walls are then removed
 /// i.e.
 /// ....
@@ -227,5 +711,505 @@ pub fn aesthetizise(mut map: ResMut<MapResource>, _worldgen: Res<WorldgenSetting
 /// ->
 /// ....
 /// ....
-/// .... */
-/* pub fn remove_random_walls(mut map: ResMut<MapResource>, worldgen: Res<WorldgenSettings>) {} */
+/// ....
+/// afterwards walkable pockets that cannot be reached from the start room are filled with walls if they are small,
+/// or connected with a carved path if they are not
+pub fn remove_random_walls(
+    map: &mut MapResource,
+    min_wall_cluster: usize,
+    min_ground_region: usize,
+    passage_width: u32,
+) -> Result<(), WorldgenError> {
+    remove_wall_specks(map, min_wall_cluster);
+    fill_or_connect_pockets(map, min_ground_region, passage_width)
+}
+
+/// wall clusters smaller than `min_wall_cluster` that do not touch the edge of their room turn into floor
+fn remove_wall_specks(map: &mut MapResource, min_wall_cluster: usize) {
+    for room in map.map_area_mut().rooms.values_mut() {
+        if !room.is_visible {
+            continue;
+        }
+        let floor = room.floor_tile();
+        for cluster in tile_clusters(room, |tile| *tile == Tile::Wall) {
+            let touches_edge = cluster.iter().any(|&p| room.is_edge_tile(p));
+            if !touches_edge && cluster.len() < min_wall_cluster {
+                for position in cluster {
+                    room.set_tile(position, floor);
+                }
+            }
+        }
+    }
+}
+
+/// 4-connected groups of tiles of a room that match the predicate, in local coordinates
+fn tile_clusters(room: &Room, predicate: impl Fn(&Tile) -> bool) -> Vec<Vec<UVec2>> {
+    let mut visited = HashSet::new();
+    let mut clusters = vec![];
+    for y in 0..room.height() as u32 {
+        for x in 0..room.length() as u32 {
+            let start = UVec2::new(x, y);
+            if visited.contains(&start) || !room.get_tile(start).is_some_and(&predicate) {
+                continue;
+            }
+            let mut cluster = vec![];
+            let mut stack = vec![start];
+            visited.insert(start);
+            while let Some(current) = stack.pop() {
+                cluster.push(current);
+                for direction in [IVec2::X, IVec2::Y, IVec2::NEG_X, IVec2::NEG_Y] {
+                    let next = current.as_ivec2() + direction;
+                    if next.x < 0 || next.y < 0 {
+                        continue;
+                    }
+                    let next = next.as_uvec2();
+                    if !visited.contains(&next) && room.get_tile(next).is_some_and(&predicate) {
+                        visited.insert(next);
+                        stack.push(next);
+                    }
+                }
+            }
+            clusters.push(cluster);
+        }
+    }
+    clusters
+}
+
+/// walkable regions that cannot be reached from the start of the main path are filled with walls
+/// if they are smaller than `min_ground_region`, otherwise a path to the reachable tiles is carved.
+/// needs the doors to be carved, rooms behind secret, locked and one-way connections are left alone
+fn fill_or_connect_pockets(
+    map: &mut MapResource,
+    min_ground_region: usize,
+    passage_width: u32,
+) -> Result<(), WorldgenError> {
+    let Some(&start_room) = map
+        .map_area()
+        .graph
+        .as_ref()
+        .and_then(|graph| graph.main_path_rooms.first())
+    else {
+        return Ok(());
+    };
+    let gated_rooms = gated_rooms(map.map_area(), start_room);
+    let mut reachable = reachable_ground_tiles(map.map_area(), start_room, &gated_rooms);
+    if reachable.is_empty() {
+        return Ok(());
+    }
+
+    for pocket in walkable_regions(map.map_area()) {
+        if pocket.iter().any(|tile| {
+            reachable.contains(tile) || is_in_rooms(map.map_area(), *tile, &gated_rooms)
+        }) {
+            continue;
+        }
+        if pocket.len() < min_ground_region {
+            for tile in pocket {
+                map.map_area_mut().set_global_tile(tile, Tile::Wall);
+            }
+            continue;
+        }
+        // start at the lowest, leftmost tile so the result does not depend on the iteration order of the set
+        let from = *pocket.iter().min_by_key(|t| (t.y, t.x)).unwrap();
+        // the outer walls are only opened by the doors
+        if carve_to_reachable(map, from, &reachable, passage_width, true, &gated_rooms)? {
+            reachable = reachable_ground_tiles(map.map_area(), start_room, &gated_rooms);
+        }
+    }
+    Ok(())
+}
+
+/// every region of walkable tiles of the visible rooms
+fn walkable_regions(map_area: &MapArea) -> Vec<HashSet<IVec2>> {
+    let mut seen = HashSet::new();
+    let mut regions = vec![];
+    for room in map_area
+        .rooms
+        .values()
+        .filter(|r| r.is_visible)
+        .sorted_by_key(|r| r.id())
+    {
+        for y in 0..room.height() as u32 {
+            for x in 0..room.length() as u32 {
+                let tile = room.local_to_global(UVec2::new(x, y));
+                if seen.contains(&tile) || !is_walkable(map_area, tile) {
+                    continue;
+                }
+                let region = walkable_region(map_area, tile, &HashSet::new());
+                seen.extend(region.iter().copied());
+                regions.push(region);
+            }
+        }
+    }
+    regions
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::map::{
+        aesthetics::{Aesthetics, Bsp, Symmetry, SymmetryMode},
+        connecting::AdjacentTiles,
+        graphing::{MyGraph, RoomGraph},
+        room::{HallwayDetails, RoomDimensions, RoomType},
+    };
+
+    /// a grid of walled rooms that touch each other. every room is connected to the one on its right,
+    /// and the rooms of the first column are connected to the one above them
+    fn walled_grid_map(columns: usize, rows: usize, room_size: usize) -> MapResource {
+        let mut map = MapResource::default();
+        let mut graph = MyGraph::default();
+        for row in 0..rows {
+            for column in 0..columns {
+                let id = row * columns + column;
+                let anchor = IVec2::new((column * room_size) as i32, (row * room_size) as i32);
+                let mut room = Room::new(id, room_size, room_size, anchor, true);
+                room.fill_edges();
+                map.map_area_mut().rooms.insert(id, room);
+                if column > 0 {
+                    graph.add_edge(id - 1, id, 1.);
+                }
+                if column == 0 && row > 0 {
+                    graph.add_edge(id - columns, id, 1.);
+                }
+            }
+        }
+        map.map_area_mut().graph = Some(RoomGraph {
+            delaunay: graph.clone(),
+            mst: graph.clone(),
+            reassembled_graph: graph,
+            main_path_rooms: vec![0],
+            hub_rooms: Vec::new(),
+        });
+        map
+    }
+
+    #[test]
+    fn test_carve_paths_with_tile_index() {
+        let mut map = walled_grid_map(3, 2, 10);
+        map.map_area_mut().rebuild_tile_index();
+        carve_path(&mut map, 6, false).unwrap();
+
+        let reachable = reachable_ground_tiles(map.map_area(), 0, &HashSet::new());
+        for room_id in 0..6 {
+            assert!(is_room_reached(map.map_area(), room_id, &reachable));
+        }
+
+        // the outer walls can be kept, so the doors are the only way between rooms
+        let mut map = walled_grid_map(3, 2, 10);
+        map.map_area_mut().rebuild_tile_index();
+        carve_path(&mut map, 6, true).unwrap();
+        for room in map.map_area().rooms.values() {
+            for y in 0..room.height() as u32 {
+                for x in 0..room.length() as u32 {
+                    let position = UVec2::new(x, y);
+                    if room.is_edge_tile(position) {
+                        assert!(room.get_tile(position) == Some(&Tile::Wall));
+                    }
+                }
+            }
+        }
+        let reachable = reachable_ground_tiles(map.map_area(), 0, &HashSet::new());
+        assert!(!is_room_reached(map.map_area(), 1, &reachable));
+    }
+
+    #[test]
+    fn test_verify_connectivity_keeps_secrets() {
+        let mut map = walled_grid_map(3, 1, 10);
+        map.map_area_mut().rebuild_tile_index();
+        let mut secret = RoomConnection::new(1, 2, RoomConnectionType::Unknown);
+        secret.attributes.push(ConnectionAttribute::Secret);
+        map.map_area_mut().connections = Some(vec![
+            RoomConnection::new(0, 1, RoomConnectionType::Unknown),
+            secret,
+        ]);
+
+        let mut report = GenerationReport::default();
+        verify_connectivity(&mut map, 4, &mut report).unwrap();
+        assert!(report.repaired_rooms == vec![1]);
+        assert!(report.gated_rooms == vec![2]);
+        // the room behind the secret connection is not carved open
+        let reachable = reachable_ground_tiles(map.map_area(), 0, &HashSet::new());
+        assert!(is_room_reached(map.map_area(), 1, &reachable));
+        assert!(!is_room_reached(map.map_area(), 2, &reachable));
+        // the door on the edge of a room does not count as reaching it
+        let door = map.map_area().rooms[&2].local_to_global(UVec2::new(0, 5));
+        let reachable = [door].into_iter().collect::<HashSet<_>>();
+        assert!(!is_room_reached(map.map_area(), 2, &reachable));
+    }
+
+    #[test]
+    fn test_carve_doors_to_center() {
+        let mut room = Room::new(0, 9, 7, IVec2::ZERO, true);
+        room.fill_edges();
+        room.set_tile(UVec2::new(0, 3), Tile::Door);
+        // the door opens into a pocket
+        for y in 1..6 {
+            room.set_tile(UVec2::new(2, y), Tile::Wall);
+        }
+        carve_doors_to_center(&mut room, &[UVec2::new(0, 3)], 1);
+        assert!(room.get_tile(UVec2::new(2, 3)) == Some(&Tile::Ground));
+        assert!(room.get_tile(UVec2::new(2, 2)) == Some(&Tile::Wall));
+        assert!(room.get_tile(UVec2::new(2, 4)) == Some(&Tile::Wall));
+        assert!(room.get_tile(UVec2::new(0, 3)) == Some(&Tile::Door));
+    }
+
+    #[test]
+    fn test_doors_attach_to_shapes() {
+        let circle = || {
+            let mut details = Room::new(0, 10, 10, IVec2::ZERO, true).details;
+            details.shape = RoomShape::Circle;
+            let dimensions = RoomDimensions {
+                anchor: IVec2::ZERO,
+                length: 10,
+                height: 10,
+            };
+            let mut room = Room::new2(0, dimensions, details);
+            room.fill_edges();
+            room
+        };
+        let rect = Room::new(1, 10, 10, IVec2::new(10, 0), true);
+        let tiles1 = (1..9).map(|y| UVec2::new(9, y)).collect_vec();
+        let tiles2 = (1..9).map(|y| UVec2::new(0, y)).collect_vec();
+        // the door goes where the circle touches the side of the room
+        assert!(door_offset(&circle(), &tiles1, &rect, &tiles2, 2) == 2);
+        let other_rect = Room::new(0, 10, 10, IVec2::ZERO, true);
+        assert!(door_offset(&other_rect, &tiles1, &rect, &tiles2, 2) == 0);
+
+        // a door outside of the circle gets a passage with walls into the room
+        let mut room = circle();
+        assert!(carve_to_footprint(&mut room, UVec2::new(0, 2)) == vec![UVec2::new(1, 2)]);
+        assert!(room.get_tile(UVec2::new(1, 2)) == Some(&Tile::Ground));
+        assert!(room.get_tile(UVec2::new(0, 1)) == Some(&Tile::Wall));
+        assert!(room.get_tile(UVec2::new(0, 2)) == Some(&Tile::Void));
+
+        // the passage stops inside of the outer walls, even if the room is full of walls
+        let mut details = Room::new(0, 10, 10, IVec2::ZERO, true).details;
+        details.shape = RoomShape::Octagon { chamfer: 3 };
+        let dimensions = RoomDimensions {
+            anchor: IVec2::ZERO,
+            length: 10,
+            height: 10,
+        };
+        let mut room = Room::new2(0, dimensions, details);
+        room.replace_tiles(Tile::Ground, Tile::Wall);
+        carve_to_footprint(&mut room, UVec2::new(0, 5));
+        assert!(room.get_tile(UVec2::new(1, 5)) == Some(&Tile::Ground));
+        assert!(room.get_tile(UVec2::new(2, 5)) == Some(&Tile::Wall));
+        assert!(room.get_tile(UVec2::new(9, 5)) == Some(&Tile::Wall));
+    }
+
+    #[test]
+    fn test_door_covers_the_passage() {
+        let mut details = Room::new(0, 10, 10, IVec2::ZERO, true).details;
+        details.shape = RoomShape::Circle;
+        let dimensions = RoomDimensions {
+            anchor: IVec2::ZERO,
+            length: 10,
+            height: 10,
+        };
+        let mut map = MapResource::default();
+        map.map_area_mut()
+            .rooms
+            .insert(0, Room::new2(0, dimensions, details));
+        map.map_area_mut()
+            .rooms
+            .insert(1, Room::new(1, 10, 10, IVec2::new(10, 0), true));
+        let adjacent_tiles = AdjacentTiles {
+            room1: (0, (0..10).map(|y| UVec2::new(9, y)).collect()),
+            room2: (1, (0..10).map(|y| UVec2::new(0, y)).collect()),
+        };
+        map.map_area_mut().connections = Some(vec![RoomConnection::new(
+            0,
+            1,
+            RoomConnectionType::Adjacent(adjacent_tiles),
+        )]);
+        carve_doors(&mut map, 8, 8).unwrap();
+
+        // the ends of the door are outside of the circle, their passages are two tiles long
+        let door = map.map_area().doors[0];
+        assert!(door.position == IVec2::new(7, 1));
+        assert!(door.size() == UVec2::new(4, 8));
+        assert!(door.tiles().any(|tile| tile == IVec2::new(7, 8)));
+    }
+
+    #[test]
+    fn test_remove_random_walls() {
+        let mut map = walled_grid_map(1, 1, 20);
+        map.map_area_mut().rebuild_tile_index();
+        let room = map.map_area_mut().rooms.get_mut(&0).unwrap();
+        // a speck of two walls in the middle of the room
+        room.set_tile(UVec2::new(10, 15), Tile::Wall);
+        room.set_tile(UVec2::new(11, 15), Tile::Wall);
+        // a 3x3 pocket in the bottom left corner and a 4x4 pocket in the bottom right corner
+        for i in 1..=4 {
+            room.set_tile(UVec2::new(4, i), Tile::Wall);
+            room.set_tile(UVec2::new(i, 4), Tile::Wall);
+            room.set_tile(UVec2::new(14, i), Tile::Wall);
+            room.set_tile(UVec2::new(14 + i, 5), Tile::Wall);
+        }
+        room.set_tile(UVec2::new(14, 5), Tile::Wall);
+
+        remove_wall_specks(&mut map, 3);
+        let room = &map.map_area().rooms[&0];
+        assert!(room.get_tile(UVec2::new(10, 15)) == Some(&Tile::Ground));
+        assert!(room.get_tile(UVec2::new(11, 15)) == Some(&Tile::Ground));
+        // walls that touch the outer walls stay
+        assert!(room.get_tile(UVec2::new(4, 2)) == Some(&Tile::Wall));
+
+        fill_or_connect_pockets(&mut map, 10, 6).unwrap();
+        let room = &map.map_area().rooms[&0];
+        // the small pocket is filled, the big one is connected
+        assert!(room.get_tile(UVec2::new(2, 2)) == Some(&Tile::Wall));
+        let reachable = reachable_ground_tiles(map.map_area(), 0, &HashSet::new());
+        assert!(reachable.contains(&IVec2::new(16, 2)));
+        assert!(walkable_regions(map.map_area()).len() == 1);
+    }
+
+    /// rooms and hallways in a row from left to right, each one connected to the next.
+    /// every second room is a hallway that is `hallway_length` tiles long
+    fn hallway_chain_map(rooms: usize, room_size: usize, hallway_length: usize) -> MapResource {
+        let mut map = MapResource::default();
+        let mut graph = MyGraph::default();
+        let mut connections = vec![];
+        let mut x = 0;
+        for id in 0..rooms {
+            let mut room = if id % 2 == 0 {
+                Room::new(id, room_size, room_size, IVec2::new(x, 0), true)
+            } else {
+                let mut hallway = Room::new(id, hallway_length, 6, IVec2::new(x, 2), false);
+                let details = HallwayDetails::new((id - 1, id + 1), 0, 4);
+                hallway.details.room_type = RoomType::Hallway(details);
+                hallway.replace_tiles(Tile::Ground, Tile::HallwayFloor);
+                hallway
+            };
+            room.fill_edges();
+            if id > 0 {
+                let left = &map.map_area().rooms[&(id - 1)];
+                // the rows both rooms share, on the right side of the left room and the left side of this room
+                let rows = room.anchor_grid().y.max(left.anchor_grid().y)
+                    ..room.anchor_grid_end().y.min(left.anchor_grid_end().y);
+                let tiles = |room: &Room, x: i32| {
+                    rows.clone()
+                        .map(|y| room.global_to_local(IVec2::new(x, y)).unwrap())
+                        .collect_vec()
+                };
+                let adjacent_tiles = AdjacentTiles {
+                    room1: (id - 1, tiles(left, x - 1)),
+                    room2: (id, tiles(&room, x)),
+                };
+                graph.add_edge(id - 1, id, 1.);
+                connections.push(RoomConnection::new(
+                    id - 1,
+                    id,
+                    RoomConnectionType::Adjacent(adjacent_tiles),
+                ));
+            }
+            x += room.length() as i32;
+            map.map_area_mut().rooms.insert(id, room);
+        }
+        map.map_area_mut().graph = Some(RoomGraph {
+            delaunay: graph.clone(),
+            mst: graph.clone(),
+            reassembled_graph: graph,
+            main_path_rooms: (0..rooms).step_by(2).collect(),
+            hub_rooms: Vec::new(),
+        });
+        map.map_area_mut().connections = Some(connections);
+        map.map_area_mut().rebuild_tile_index();
+        map
+    }
+
+    #[test]
+    fn test_hallways_survive_cleanup() {
+        // the inside of every hallway is smaller than the smallest region that is kept
+        let mut map = hallway_chain_map(7, 10, 6);
+        carve_doors(&mut map, 2, 4).unwrap();
+        remove_random_walls(&mut map, 3, 20, 2).unwrap();
+
+        for hallway in map.map_area().hallways() {
+            for y in 1..hallway.height() as u32 - 1 {
+                for x in 1..hallway.length() as u32 - 1 {
+                    assert!(hallway.get_tile(UVec2::new(x, y)) == Some(&Tile::HallwayFloor));
+                }
+            }
+        }
+        let reachable = reachable_ground_tiles(map.map_area(), 0, &HashSet::new());
+        for room_id in 0..7 {
+            assert!(is_room_reached(map.map_area(), room_id, &reachable));
+        }
+        assert!(walkable_regions(map.map_area()).len() == 1);
+    }
+
+    #[test]
+    fn test_symmetry_after_bsp() {
+        for seed in 0..10 {
+            let mut map = hallway_chain_map(3, 30, 6);
+            let room = map.map_area_mut().rooms.get_mut(&2).unwrap();
+            room.details.aesthetic_modifiers = vec![
+                Aesthetics::Bsp(Bsp {
+                    depth: 3,
+                    min_cell_size: 3,
+                    door_width: 2,
+                }),
+                Aesthetics::Symmetry(Symmetry {
+                    mode: SymmetryMode::FourWay,
+                }),
+            ];
+            let mut rng = WorldgenRng::new(seed);
+            aesthetizise(&mut map, &mut rng, false, false, 2, 4).unwrap();
+            carve_doors(&mut map, 2, 4).unwrap();
+            aesthetizise(&mut map, &mut rng, false, true, 2, 4).unwrap();
+            // the mirrored walls can cut off parts of the chambers, the cleanup fills or connects them
+            remove_random_walls(&mut map, 3, 12, 2).unwrap();
+
+            let reachable = reachable_ground_tiles(map.map_area(), 0, &HashSet::new());
+            assert!(is_room_reached(map.map_area(), 2, &reachable));
+            assert!(walkable_regions(map.map_area()).len() == 1);
+        }
+    }
+
+    /// carving on a 50 room map, with and without the tile index.
+    /// run with `cargo test --release carve_benchmark -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn carve_benchmark() {
+        let mut linear_map = walled_grid_map(10, 5, 20);
+        let mut indexed_map = walled_grid_map(10, 5, 20);
+        indexed_map.map_area_mut().rebuild_tile_index();
+
+        let start = Instant::now();
+        carve_path(&mut linear_map, 6, false).unwrap();
+        let linear_time = start.elapsed();
+
+        let start = Instant::now();
+        carve_path(&mut indexed_map, 6, false).unwrap();
+        let indexed_time = start.elapsed();
+
+        println!("carving 50 rooms: linear lookup {linear_time:?}, tile index {indexed_time:?}");
+    }
+
+    #[test]
+    fn test_carve_paths_without_tile_index() {
+        let mut linear_map = walled_grid_map(3, 2, 10);
+        let mut indexed_map = walled_grid_map(3, 2, 10);
+        indexed_map.map_area_mut().rebuild_tile_index();
+        carve_path(&mut linear_map, 6, false).unwrap();
+        carve_path(&mut indexed_map, 6, false).unwrap();
+
+        // both lookups have to carve the exact same tiles
+        for linear in linear_map.map_area().rooms.values() {
+            for y in 0..linear.height() {
+                for x in 0..linear.length() {
+                    let tile = linear.local_to_global(UVec2::new(x as u32, y as u32));
+                    assert!(
+                        linear_map.map_area().global_tile(tile)
+                            == indexed_map.map_area().global_tile(tile)
+                    );
+                }
+            }
+        }
+    }
+}
@@ -7,15 +7,23 @@ use bevy::{prelude::*, utils::HashMap};
 
 use serde::{Deserialize, Serialize};
 
-use crate::map::room::{RoomDetails, RoomDimensions, RoomWithDetailsNoId, StructureCollection};
+use crate::map::room::{
+    RoomDetails, RoomDimensions, RoomShape, RoomWithDetailsNoId, StructureCollection,
+};
 
-use super::{aesthetics, generation::WorldgenRng};
+use super::{
+    aesthetics,
+    connecting::ConnectionAttribute,
+    generation::{WorldgenError, WorldgenRng},
+};
 
 #[derive(Deserialize, Debug, Clone, Default)]
 pub struct PresetsConfig {
     pub start: Vec<String>,
     pub normal: Vec<String>,
     pub boss: Vec<String>,
+    #[serde(default)]
+    pub hub_aesthetics: Vec<aesthetics::Aesthetics>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -31,6 +39,8 @@ pub struct PresetRoom {
     pub size: PresetRoomSize,
     pub position: PresetRoomPosition,
     pub aesthetics: Vec<aesthetics::Aesthetics>,
+    #[serde(default)]
+    pub shape: RoomShape,
 }
 
 #[derive(Resource, Default, Debug)]
@@ -51,15 +61,19 @@ impl RoomPresetResource {
     pub fn get_preset_by_type(&self, input: &str, rng: &mut WorldgenRng) -> Option<Preset> {
         let names = self.get_preset_names_in_type(input);
         match names {
-            Some(inner) => {
+            Some(inner) if !inner.is_empty() => {
                 // just pick a random one
                 let random_choice = inner[rng.usize(0..inner.len())].as_str();
                 self.get_preset_by_name(random_choice)
             }
-            None => return None,
+            _ => return None,
         }
     }
 
+    pub fn hub_aesthetics(&self) -> &[aesthetics::Aesthetics] {
+        &self.config.hub_aesthetics
+    }
+
     fn get_preset_names_in_type(&self, input: &str) -> Option<Vec<String>> {
         let preset_names = match input {
             "start" => self.config.start.clone(),
@@ -115,6 +129,8 @@ impl PresetInfoSource<IVec2> for PresetRoomPosition {
 pub struct PresetRoomConnection {
     pub room1: String,
     pub room2: String,
+    #[serde(default)]
+    pub attributes: Vec<ConnectionAttribute>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -152,7 +168,7 @@ fn read_all_presets() -> io::Result<Vec<Preset>> {
     let mut presets = Vec::new();
     for file_name in file_names.iter() {
         let file_path = format!("{}/{}", path, file_name);
-        let file_contents = fs::read_to_string(file_path).unwrap();
+        let file_contents = fs::read_to_string(file_path)?;
         let result = ron::de::from_str::<Preset>(&file_contents);
         match result {
             Ok(preset) => presets.push(preset),
@@ -167,7 +183,7 @@ fn read_all_presets() -> io::Result<Vec<Preset>> {
 fn read_config() -> Result<PresetsConfig, ()> {
     let path = "room_generator/assets/worldgen/config.ron";
 
-    let file_contents = fs::read_to_string(path).unwrap();
+    let file_contents = fs::read_to_string(path).map_err(|_| ())?;
     let result = ron::de::from_str::<PresetsConfig>(&file_contents);
     match result {
         Ok(x) => Ok(x),
@@ -178,10 +194,13 @@ fn read_config() -> Result<PresetsConfig, ()> {
 pub fn generate_rooms_from_preset(
     preset: &Preset,
     rng: &mut WorldgenRng,
-) -> (
-    StructureCollection<RoomWithDetailsNoId>,
-    Vec<(usize, usize)>,
-) {
+) -> Result<
+    (
+        StructureCollection<RoomWithDetailsNoId>,
+        Vec<(usize, usize, Vec<ConnectionAttribute>)>,
+    ),
+    WorldgenError,
+> {
     let mut rooms = vec![];
     let mut connections = vec![];
     let mut name_to_id_map = HashMap::new();
@@ -212,14 +231,21 @@ pub fn generate_rooms_from_preset(
             details,
         });
     }
+    let room_id = |name: &String| {
+        name_to_id_map
+            .get(name)
+            .copied()
+            .ok_or_else(|| WorldgenError::UnknownPresetRoom(format!("{}: {}", preset.name, name)))
+    };
     for connection in preset.connections.iter() {
         connections.push((
-            *name_to_id_map.get(&connection.room1).unwrap(),
-            *name_to_id_map.get(&connection.room2).unwrap(),
+            room_id(&connection.room1)?,
+            room_id(&connection.room2)?,
+            connection.attributes.clone(),
         ))
     }
 
-    (StructureCollection::new(rooms), connections)
+    Ok((StructureCollection::new(rooms), connections))
 }
 
 fn calculate_dimensions(preset_room: &PresetRoom, rng: &mut WorldgenRng) -> RoomDimensions {
@@ -238,6 +264,8 @@ fn get_details(preset_room: &PresetRoom) -> RoomDetails {
         is_main: false,
         room_type: super::room::RoomType::Normal,
         aesthetic_modifiers: preset_room.aesthetics.clone(),
+        keys: Vec::new(),
+        shape: preset_room.shape.clone(),
     }
 }
 
@@ -0,0 +1,443 @@
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use itertools::Itertools;
+
+use super::{
+    connecting::{ConnectionAttribute, RoomConnection},
+    generation::{MapArea, MapResource, WorldgenError, WorldgenRng, WorldgenSettings},
+    graphing::MyGraph,
+    util::edge_key,
+};
+
+/// turns some connections along the main path into locked doors and places the matching key
+/// in a room that can be reached before that door. side branches are preferred for keys, so the
+/// player has to leave the main path to progress.
+/// only connections without a loop around them are locked, otherwise the lock would not gate anything.
+/// before the locks the other attributes are handed out, see `assign_connection_attributes`
+///
+/// this runs after `remake_graphs`, so the graph only contains edges between adjacent rooms
+pub fn assign_locks_and_keys(
+    map: &mut MapResource,
+    worldgen: &WorldgenSettings,
+    rng: &mut WorldgenRng,
+) -> Result<(), WorldgenError> {
+    let Some(room_graph) = map.map_area().graph.clone() else {
+        return Err(WorldgenError::MissingStageOutput("graph".into()));
+    };
+    let graph = room_graph.reassembled_graph;
+    let (Some(&start), Some(&end)) = (
+        room_graph.main_path_rooms.first(),
+        room_graph.main_path_rooms.last(),
+    ) else {
+        return Ok(());
+    };
+
+    // the main path only knows about main rooms, so find the actual path through the hallways
+    let Some(path) = path_between(&graph, start, end) else {
+        warn!("no path between start room {start} and end room {end}, no locks are placed");
+        return Ok(());
+    };
+    let path_edges = path
+        .iter()
+        .tuple_windows()
+        .map(|(&a, &b)| edge_key(a, b))
+        .collect_vec();
+
+    let boss_gate = assign_connection_attributes(map, worldgen, &graph, start, &path_edges, rng);
+    let lockable_edges = path_edges
+        .iter()
+        .copied()
+        .filter(|&edge| Some(edge) != boss_gate && cuts_off(&graph, start, edge))
+        .collect_vec();
+
+    // spread the locks evenly along the path
+    let locks_amount = worldgen.locks_amount.min(lockable_edges.len());
+    let mut locked_edges = (1..=locks_amount)
+        .map(|i| lockable_edges[i * lockable_edges.len() / (locks_amount + 1)])
+        .dedup()
+        .collect_vec();
+    // presets can ask for locked connections too, those still need a key
+    let connections = map.map_area().connections.clone().unwrap_or_default();
+    for connection in connections.iter() {
+        let edge = edge_key(connection.room1_id, connection.room2_id);
+        if connection.has_attribute(ConnectionAttribute::Locked(None))
+            && !locked_edges.contains(&edge)
+        {
+            if !cuts_off(&graph, start, edge) {
+                warn!("the locked connection {edge:?} of the preset can be walked around");
+            }
+            locked_edges.push(edge);
+        }
+    }
+    // keys are handed out from the start room outward
+    let depths = room_depths(&graph, start);
+    locked_edges.sort_by_key(|(a, b)| {
+        depths
+            .get(a)
+            .copied()
+            .unwrap_or(usize::MAX)
+            .min(depths.get(b).copied().unwrap_or(usize::MAX))
+    });
+
+    let one_way = one_way_blocked(&connections);
+    for (key_id, &locked_edge) in locked_edges.iter().enumerate() {
+        // every lock from this one onward is closed, everything before can already be opened
+        let blocked = locked_edges[key_id..]
+            .iter()
+            .copied()
+            .collect::<HashSet<_>>();
+        let reachable = reachable_rooms(&graph, start, |a, b| {
+            !blocked.contains(&edge_key(a, b)) && !one_way.contains(&(a, b))
+        });
+
+        let Some(key_room) = choose_key_room(map.map_area(), &reachable, &path, rng) else {
+            warn!("no room found for key {key_id}");
+            continue;
+        };
+
+        map.map_area_mut()
+            .rooms
+            .get_mut(&key_room)
+            .ok_or(WorldgenError::UnknownRoom(key_room))?
+            .details
+            .keys
+            .push(key_id);
+        for connection in map.map_area_mut().connections.iter_mut().flatten() {
+            if edge_key(connection.room1_id, connection.room2_id) == locked_edge {
+                connection
+                    .attributes
+                    .retain(|a| !matches!(a, ConnectionAttribute::Locked(_)));
+                connection
+                    .attributes
+                    .push(ConnectionAttribute::Locked(Some(key_id)));
+            }
+        }
+    }
+
+    if !is_solvable(map.map_area(), start) {
+        warn!("lock and key placement is not solvable, removing all locks");
+        for connection in map.map_area_mut().connections.iter_mut().flatten() {
+            connection
+                .attributes
+                .retain(|a| !matches!(a, ConnectionAttribute::Locked(_)));
+        }
+        for room in map.map_area_mut().rooms.values_mut() {
+            room.details.keys.clear();
+        }
+    }
+    Ok(())
+}
+
+/// hides `secrets_amount` connections of loops behind secret walls and turns `one_way_amount` of them into drops,
+/// the rest of the loop is the way around them. with `boss_gate` the last connection of the main path becomes a boss gate.
+/// connections that already have attributes (from presets) are left alone. returns the edge of the boss gate
+fn assign_connection_attributes(
+    map: &mut MapResource,
+    worldgen: &WorldgenSettings,
+    graph: &MyGraph,
+    start: usize,
+    path_edges: &[(usize, usize)],
+    rng: &mut WorldgenRng,
+) -> Option<(usize, usize)> {
+    let connections = map.map_area_mut().connections.as_mut()?;
+    let mut loop_connections = connections
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| c.attributes.is_empty())
+        .map(|(i, c)| (edge_key(c.room1_id, c.room2_id), i))
+        .filter(|(edge, _)| !path_edges.contains(edge) && !cuts_off(graph, start, *edge))
+        .sorted()
+        .collect_vec();
+    rng.shuffle(&mut loop_connections);
+
+    // the secrets are handed out first
+    let mut wanted = [
+        (ConnectionAttribute::OneWay, worldgen.one_way_amount),
+        (ConnectionAttribute::Secret, worldgen.secrets_amount),
+    ]
+    .into_iter()
+    .flat_map(|(attribute, amount)| std::iter::repeat(attribute).take(amount))
+    .collect_vec();
+    // every room has to stay reachable even if none of these connections could be used,
+    // so two of them never close both ways around the same loop
+    let all_rooms = reachable_rooms(graph, start, |_, _| true).len();
+    let mut closed = HashSet::new();
+    for (edge, index) in loop_connections {
+        let Some(&attribute) = wanted.last() else {
+            break;
+        };
+        closed.insert(edge);
+        let reachable = reachable_rooms(graph, start, |a, b| !closed.contains(&edge_key(a, b)));
+        if reachable.len() < all_rooms {
+            closed.remove(&edge);
+            continue;
+        }
+        wanted.pop();
+        connections[index].attributes.push(attribute);
+    }
+
+    if !worldgen.boss_gate {
+        return None;
+    }
+    let boss_gate = *path_edges.last()?;
+    connections
+        .iter_mut()
+        .find(|c| edge_key(c.room1_id, c.room2_id) == boss_gate && c.attributes.is_empty())?
+        .attributes
+        .push(ConnectionAttribute::BossGate);
+    Some(boss_gate)
+}
+
+/// picks a room for a key out of the reachable rooms.
+/// main rooms that are not on the path (side branches) are preferred, then main rooms on the path, then anything else
+fn choose_key_room(
+    map_area: &MapArea,
+    reachable: &HashSet<usize>,
+    path: &[usize],
+    rng: &mut WorldgenRng,
+) -> Option<usize> {
+    let candidates = reachable
+        .iter()
+        .copied()
+        .filter(|id| {
+            map_area
+                .rooms
+                .get(id)
+                .is_some_and(|room| room.details.keys.is_empty())
+        })
+        .sorted()
+        .collect_vec();
+    let is_main = |id: &usize| map_area.rooms[id].details.is_main;
+
+    let side_branches = candidates
+        .iter()
+        .copied()
+        .filter(|id| is_main(id) && !path.contains(id))
+        .collect_vec();
+    let on_path = candidates.iter().copied().filter(is_main).collect_vec();
+
+    [side_branches, on_path, candidates]
+        .into_iter()
+        .find(|list| !list.is_empty())
+        .map(|list| list[rng.usize(0..list.len())])
+}
+
+/// simulates a player that starts in the start room, picks up every key it can reach and opens every door it has a key for.
+/// returns true if every room of the graph can be reached that way
+pub fn is_solvable(map_area: &MapArea, start: usize) -> bool {
+    let Some(graph) = map_area.graph.as_ref().map(|g| &g.reassembled_graph) else {
+        return false;
+    };
+    let connections = map_area.connections.as_deref().unwrap_or_default();
+    let locks = connection_locks(connections);
+    let one_way = one_way_blocked(connections);
+
+    let mut keys = HashSet::new();
+    loop {
+        let reachable = reachable_rooms(graph, start, |a, b| {
+            !one_way.contains(&(a, b))
+                && locks
+                    .get(&edge_key(a, b))
+                    .map_or(true, |key| keys.contains(key))
+        });
+        let keys_before = keys.len();
+        for room in reachable.iter().filter_map(|id| map_area.rooms.get(id)) {
+            keys.extend(room.details.keys.iter().copied());
+        }
+        if keys.len() == keys_before {
+            return reachable.len() == graph.node_count();
+        }
+    }
+}
+
+/// all locked connections, keyed by the (sorted) pair of room ids
+fn connection_locks(connections: &[RoomConnection]) -> HashMap<(usize, usize), usize> {
+    connections
+        .iter()
+        .filter_map(|c| c.lock().map(|key| (edge_key(c.room1_id, c.room2_id), key)))
+        .collect()
+}
+
+/// the directions (from, to) that cannot be walked because of one-way connections
+fn one_way_blocked(connections: &[RoomConnection]) -> HashSet<(usize, usize)> {
+    connections
+        .iter()
+        .filter(|c| c.has_attribute(ConnectionAttribute::OneWay))
+        .map(|c| (c.room2_id, c.room1_id))
+        .collect()
+}
+
+/// true if one side of the edge cannot be reached from the start room without walking over it,
+/// i.e. a lock on the edge actually cuts off the rooms behind it
+fn cuts_off(graph: &MyGraph, start: usize, edge: (usize, usize)) -> bool {
+    let reachable = reachable_rooms(graph, start, |a, b| edge_key(a, b) != edge);
+    !reachable.contains(&edge.0) || !reachable.contains(&edge.1)
+}
+
+/// amount of edges between the start room and every other room, ignoring locks
+fn room_depths(graph: &MyGraph, start: usize) -> HashMap<usize, usize> {
+    let mut depths = HashMap::new();
+    if !graph.contains_node(start) {
+        return depths;
+    }
+    let mut queue = std::collections::VecDeque::from([start]);
+    depths.insert(start, 0);
+    while let Some(current) = queue.pop_front() {
+        for neighbor in graph.neighbors(current) {
+            if !depths.contains_key(&neighbor) {
+                depths.insert(neighbor, depths[&current] + 1);
+                queue.push_back(neighbor);
+            }
+        }
+    }
+    depths
+}
+
+/// flood fills the graph from start. edges for which `can_pass` returns false are not used
+pub fn reachable_rooms(
+    graph: &MyGraph,
+    start: usize,
+    can_pass: impl Fn(usize, usize) -> bool,
+) -> HashSet<usize> {
+    let mut reachable = HashSet::new();
+    if !graph.contains_node(start) {
+        return reachable;
+    }
+    let mut stack = vec![start];
+    reachable.insert(start);
+    while let Some(current) = stack.pop() {
+        for neighbor in graph.neighbors(current) {
+            if can_pass(current, neighbor) && reachable.insert(neighbor) {
+                stack.push(neighbor);
+            }
+        }
+    }
+    reachable
+}
+
+fn path_between(graph: &MyGraph, start: usize, end: usize) -> Option<Vec<usize>> {
+    if !graph.contains_node(start) {
+        return None;
+    }
+    pathfinding::prelude::bfs(
+        &start,
+        |current| graph.neighbors(*current).sorted().collect_vec(),
+        |current| *current == end,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{connecting::RoomConnectionType, graphing::RoomGraph, room::Room};
+
+    fn chain_map_area(key_room: usize) -> MapArea {
+        let mut rooms = HashMap::new();
+        for id in 0..3 {
+            rooms.insert(id, Room::new(id, 5, 5, IVec2::new(id as i32 * 5, 0), true));
+        }
+        rooms.get_mut(&key_room).unwrap().details.keys.push(0);
+
+        let mut graph = MyGraph::default();
+        graph.add_edge(0, 1, 1.);
+        graph.add_edge(1, 2, 1.);
+
+        let mut locked = RoomConnection::new(1, 2, RoomConnectionType::Unknown);
+        locked.attributes.push(ConnectionAttribute::Locked(Some(0)));
+
+        MapArea {
+            rooms,
+            graph: Some(RoomGraph {
+                delaunay: graph.clone(),
+                mst: graph.clone(),
+                reassembled_graph: graph,
+                main_path_rooms: vec![0, 1, 2],
+                hub_rooms: Vec::new(),
+            }),
+            connections: Some(vec![
+                RoomConnection::new(0, 1, RoomConnectionType::Unknown),
+                locked,
+            ]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_is_solvable() {
+        assert!(is_solvable(&chain_map_area(0), 0));
+        assert!(is_solvable(&chain_map_area(1), 0));
+        // the key is behind its own door
+        assert!(!is_solvable(&chain_map_area(2), 0));
+    }
+
+    #[test]
+    fn test_is_solvable_one_way() {
+        let mut map_area = chain_map_area(1);
+        let connections = map_area.connections.as_mut().unwrap();
+        connections[0].attributes.push(ConnectionAttribute::OneWay);
+        assert!(is_solvable(&map_area, 0));
+        // the drop goes from 0 to 1, so room 0 cannot be reached from room 1
+        assert!(!is_solvable(&map_area, 1));
+    }
+
+    #[test]
+    fn test_locks_cut_off_rooms() {
+        // 0 - 1 - 2 - 3 is a loop, 4 hangs off of 3
+        let mut graph = MyGraph::default();
+        graph.add_edge(0, 1, 1.);
+        graph.add_edge(1, 2, 1.);
+        graph.add_edge(2, 3, 1.);
+        graph.add_edge(3, 0, 1.);
+        graph.add_edge(3, 4, 1.);
+        assert!(!cuts_off(&graph, 0, edge_key(0, 3)));
+        assert!(!cuts_off(&graph, 0, edge_key(1, 2)));
+        assert!(cuts_off(&graph, 0, edge_key(3, 4)));
+        assert!(cuts_off(&graph, 4, edge_key(3, 4)));
+    }
+
+    #[test]
+    fn test_assign_connection_attributes() {
+        // 0 - 1 - 2 - 3 is a loop, 4 hangs off of 3. the main path is 0 - 3 - 4
+        let mut map = MapResource::default();
+        let mut graph = MyGraph::default();
+        let edges = [(0, 1), (1, 2), (2, 3), (0, 3), (3, 4)];
+        for (a, b) in edges {
+            graph.add_edge(a, b, 1.);
+        }
+        map.map_area_mut().connections = Some(
+            edges
+                .iter()
+                .map(|&(a, b)| RoomConnection::new(a, b, RoomConnectionType::Unknown))
+                .collect(),
+        );
+        let worldgen = WorldgenSettings {
+            secrets_amount: 1,
+            one_way_amount: 1,
+            boss_gate: true,
+            ..default()
+        };
+        let path_edges = [(0, 3), (3, 4)];
+        let boss_gate = assign_connection_attributes(
+            &mut map,
+            &worldgen,
+            &graph,
+            0,
+            &path_edges,
+            &mut WorldgenRng::new(0),
+        );
+        assert!(boss_gate == Some((3, 4)));
+
+        let connections = map.map_area().connections.clone().unwrap();
+        assert!(connections[4].attributes == vec![ConnectionAttribute::BossGate]);
+        assert!(connections[3].attributes.is_empty());
+        // only one connection of the loop can be closed, otherwise room 1 or 2 would be cut off
+        let closed = connections[..3]
+            .iter()
+            .filter(|c| !c.attributes.is_empty())
+            .collect_vec();
+        assert!(closed.len() == 1);
+        assert!(closed[0].attributes == vec![ConnectionAttribute::Secret]);
+    }
+}
@@ -81,19 +81,95 @@ where
     fn offset_anchor_grid(&mut self, _offset: IVec2) {
         println!("offset anchor grid called with reference.....");
     }
+
+    fn is_point_inside(&self, point: (i32, i32)) -> bool {
+        T::is_point_inside(*self, point)
+    }
 }
 
 pub trait Structure: StructureDimensions {
     fn id(&self) -> usize;
 }
 
-#[derive(Debug, Clone, Copy, Default)]
+/// every kind of tile a room can be made of. what a tile does is looked up in `TILE_PROPERTIES`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, serde::Deserialize)]
 pub enum Tile {
     #[default]
     Ground,
     Wall,
+    Door,
+    // floor variants
+    Carpet,
+    HallwayFloor,
+    Rubble,
+    // hazards
+    Pit,
+    Water,
+    Lava,
+    // the edge of a drop into the next room, see `ConnectionAttribute::OneWay`
+    Ledge,
+    // outside of the footprint of a shaped room, i.e. not part of the room at all
+    Void,
+}
+impl Tile {
+    pub fn properties(&self) -> &'static TileProperties {
+        &TILE_PROPERTIES[*self as usize]
+    }
+    pub fn is_walkable(&self) -> bool {
+        self.properties().walkable
+    }
+    pub fn blocks_sight(&self) -> bool {
+        self.properties().blocks_sight
+    }
+    pub fn damage(&self) -> u32 {
+        self.properties().damage
+    }
+    /// tiles that can be walked on without getting hurt. paths are carved through everything else
+    pub fn is_safe(&self) -> bool {
+        self.is_walkable() && self.damage() == 0
+    }
+    /// ground and its variants
+    pub fn is_floor(&self) -> bool {
+        matches!(
+            self,
+            Tile::Ground | Tile::Carpet | Tile::HallwayFloor | Tile::Rubble
+        )
+    }
+    /// the tile with this symbol in `TILE_PROPERTIES`
+    pub fn from_symbol(symbol: char) -> Option<Tile> {
+        TILE_PROPERTIES
+            .iter()
+            .find(|p| p.symbol == symbol)
+            .map(|p| p.tile)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TileProperties {
+    pub tile: Tile,
+    pub walkable: bool,
+    pub blocks_sight: bool,
+    pub damage: u32,         // damage per second while standing on the tile
+    pub color: &'static str, // hex color used by spawn_rooms_visuals
+    pub symbol: char,        // used by the text export
 }
 
+/// properties of every tile, in the same order as the variants of `Tile`
+#[rustfmt::skip]
+pub const TILE_PROPERTIES: [TileProperties; 11] = [
+    TileProperties { tile: Tile::Ground,       walkable: true,  blocks_sight: false, damage: 0, color: "#222222", symbol: '.' },
+    TileProperties { tile: Tile::Wall,         walkable: false, blocks_sight: true,  damage: 0, color: "#777777", symbol: '#' },
+    TileProperties { tile: Tile::Door,         walkable: true,  blocks_sight: false, damage: 0, color: "#8b5a2b", symbol: '+' },
+    TileProperties { tile: Tile::Carpet,       walkable: true,  blocks_sight: false, damage: 0, color: "#5c1f2b", symbol: ',' },
+    TileProperties { tile: Tile::HallwayFloor, walkable: true,  blocks_sight: false, damage: 0, color: "#2e2a24", symbol: ':' },
+    TileProperties { tile: Tile::Rubble,       walkable: true,  blocks_sight: false, damage: 0, color: "#4a4540", symbol: '%' },
+    TileProperties { tile: Tile::Pit,          walkable: false, blocks_sight: false, damage: 0, color: "#000000", symbol: 'o' },
+    TileProperties { tile: Tile::Water,        walkable: true,  blocks_sight: false, damage: 0, color: "#1f4e8c", symbol: '~' },
+    TileProperties { tile: Tile::Lava,         walkable: true,  blocks_sight: false, damage: 5, color: "#c83c0a", symbol: '^' },
+    TileProperties { tile: Tile::Ledge,        walkable: true,  blocks_sight: false, damage: 0, color: "#3d2b1f", symbol: 'v' },
+    TileProperties { tile: Tile::Void,         walkable: false, blocks_sight: false, damage: 0, color: "#000000", symbol: ' ' },
+];
+
 #[derive(Debug, Clone, Copy, Reflect, PartialEq, Eq)]
 pub enum DoorOrientation {
     Vertical,
@@ -139,6 +215,112 @@ pub enum RoomType {
     Normal,
     Shop,
     Boss,
+    Hallway(HallwayDetails), // created while connecting rooms, not spawned from a preset
+    Hub(HallwayDetails),     // a hallway made into a small room at a bend or crossing
+}
+impl RoomType {
+    /// name of the variant without any data, i.e. for exporting
+    pub fn name(&self) -> &'static str {
+        match self {
+            RoomType::Normal => "Normal",
+            RoomType::Shop => "Shop",
+            RoomType::Boss => "Boss",
+            RoomType::Hallway(_) => "Hallway",
+            RoomType::Hub(_) => "Hub",
+        }
+    }
+}
+
+/// what a hallway room was made for
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HallwayDetails {
+    pub segments: Vec<HallwaySegment>, // merged hallways are part of more than one connection
+    pub width: u32,                    // width of the passage, not the length of the segment
+}
+impl HallwayDetails {
+    pub fn new(connection: (usize, usize), index: usize, width: u32) -> Self {
+        Self {
+            segments: vec![HallwaySegment { connection, index }],
+            width,
+        }
+    }
+    /// the segment of the connection between the two rooms, in any order
+    pub fn segment_of(&self, room1_id: usize, room2_id: usize) -> Option<&HallwaySegment> {
+        self.segments
+            .iter()
+            .find(|s| s.connection == (room1_id, room2_id) || s.connection == (room2_id, room1_id))
+    }
+}
+
+/// the place of a hallway in the chain of hallways that was built for a connection
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HallwaySegment {
+    pub connection: (usize, usize), // (room1_id, room2_id) of the connection this hallway was built for
+    pub index: usize,               // 0 is the segment next to room1
+}
+
+/// the footprint of a room inside of its bounding box. every tile outside of the footprint is `Tile::Void`.
+/// the sizes are relative to the size of the room, so a shape works for every room size
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize)]
+pub enum RoomShape {
+    #[default]
+    Rect,
+    Circle,  // the biggest circle that fits into the room, in the middle
+    Ellipse, // touches all four sides of the room
+    Cross {
+        arm_width: f32, // 0.0..=1.0
+    },
+    L {
+        arm_width: f32, // 0.0..=1.0, the top right corner is cut off
+    },
+    Octagon {
+        chamfer: u32, // tiles cut off diagonally at every corner
+    },
+    Polygon(Vec<(f32, f32)>), // corners from (0, 0) (bottom left) to (1, 1) (top right)
+}
+impl RoomShape {
+    /// is the tile at `position` part of the footprint of a room with the given size.
+    /// a tile belongs to the footprint if its middle is inside of the shape
+    pub fn contains(&self, position: UVec2, length: usize, height: usize) -> bool {
+        let size = Vec2::new(length as f32, height as f32);
+        let point = position.as_vec2() + 0.5;
+        let from_center = point - size / 2.;
+        match self {
+            RoomShape::Rect => true,
+            RoomShape::Circle => from_center.length() <= size.min_element() / 2.,
+            RoomShape::Ellipse => (from_center / (size / 2.)).length_squared() <= 1.,
+            RoomShape::Cross { arm_width } => {
+                let arm = size * *arm_width / 2.;
+                from_center.x.abs() <= arm.x || from_center.y.abs() <= arm.y
+            }
+            RoomShape::L { arm_width } => {
+                let arm = size * *arm_width;
+                point.x <= arm.x || point.y <= arm.y
+            }
+            RoomShape::Octagon { chamfer } => {
+                let x = position.x.min(length as u32 - 1 - position.x);
+                let y = position.y.min(height as u32 - 1 - position.y);
+                x + y >= *chamfer
+            }
+            RoomShape::Polygon(corners) => {
+                if corners.len() < 3 {
+                    return true;
+                }
+                // even-odd rule: count the edges that a ray to the right crosses
+                let point = point / size;
+                let mut is_inside = false;
+                for (i, &(x1, y1)) in corners.iter().enumerate() {
+                    let (x2, y2) = corners[(i + 1) % corners.len()];
+                    if (y1 > point.y) != (y2 > point.y)
+                        && point.x < x1 + (point.y - y1) / (y2 - y1) * (x2 - x1)
+                    {
+                        is_inside = !is_inside;
+                    }
+                }
+                is_inside
+            }
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -146,6 +328,8 @@ pub struct RoomDetails {
     pub is_main: bool,
     pub room_type: RoomType,
     pub aesthetic_modifiers: Vec<aesthetics::Aesthetics>,
+    pub keys: Vec<usize>, // ids of the keys that are placed inside this room
+    pub shape: RoomShape,
 }
 
 #[derive(Debug, Clone, Component)]
@@ -173,11 +357,34 @@ impl Room {
                 is_main,
                 room_type: RoomType::Normal,
                 aesthetic_modifiers: Vec::new(),
+                keys: Vec::new(),
+                shape: RoomShape::Rect,
             },
             is_position_fixed: false,
             is_visible: true,
         }
     }
+    pub fn is_hallway(&self) -> bool {
+        matches!(self.details.room_type, RoomType::Hallway(_))
+    }
+    pub fn is_hub(&self) -> bool {
+        matches!(self.details.room_type, RoomType::Hub(_))
+    }
+    /// the floor tile that carving uses in this room
+    pub fn floor_tile(&self) -> Tile {
+        if self.is_hallway() {
+            Tile::HallwayFloor
+        } else {
+            Tile::Ground
+        }
+    }
+    /// the details of hallways and of the hubs that were made out of hallways
+    pub fn hallway_details(&self) -> Option<&HallwayDetails> {
+        match &self.details.room_type {
+            RoomType::Hallway(details) | RoomType::Hub(details) => Some(details),
+            _ => None,
+        }
+    }
     // only used for unit tests
     pub fn _new_only_spatial(length: usize, height: usize, world_pos: IVec2) -> Self {
         Self {
@@ -188,6 +395,8 @@ impl Room {
                 is_main: true,
                 room_type: RoomType::Normal,
                 aesthetic_modifiers: Vec::new(),
+                keys: Vec::new(),
+                shape: RoomShape::Rect,
             },
             is_position_fixed: false,
             is_visible: true,
@@ -204,19 +413,33 @@ impl Room {
         )
     } */
 
+    /// the tiles outside of `details.shape` are Void from the start
     pub fn new2(
         room_id: usize,
         dimensions: impl StructureDimensions,
         details: RoomDetails,
     ) -> Self {
-        Self {
+        let mut room = Self {
             room_id,
             data: grid::Grid::new(dimensions.height(), dimensions.length()),
             world_pos: dimensions.anchor_grid(),
             details,
             is_position_fixed: false,
             is_visible: true,
+        };
+        for y in 0..room.height() {
+            for x in 0..room.length() {
+                let position = UVec2::new(x as u32, y as u32);
+                if !room
+                    .details
+                    .shape
+                    .contains(position, room.length(), room.height())
+                {
+                    *room.data.get_mut(y, x).unwrap() = Tile::Void;
+                }
+            }
         }
+        room
     }
 
     #[inline(always)]
@@ -228,16 +451,54 @@ impl Room {
     pub fn get_tile(&self, position: UVec2) -> Option<&Tile> {
         self.data.get(position.y as usize, position.x as usize)
     }
-    /// sets the tile to a new Tile. if the tile doesnt exist (position doesnt apply to this tile), then false is returned. else true
+    /// sets the tile to a new Tile. if the tile doesnt exist (position doesnt apply to this tile), then false is returned. else true.
+    /// Void tiles are not part of the room, so they are never overwritten (see `extend_footprint`)
     #[inline(always)]
     pub fn set_tile(&mut self, position: UVec2, tile: Tile) -> bool {
-        let t = self.data.get_mut(position.y as usize, position.x as usize);
-        if t.is_none() {
+        let Some(t) = self.data.get_mut(position.y as usize, position.x as usize) else {
+            return false;
+        };
+        if *t == Tile::Void {
             return false;
         }
-        *t.unwrap() = tile;
+        *t = tile;
+        true
+    }
+    /// like `set_tile`, but Void tiles are overwritten too, so the tile becomes part of the room
+    pub fn extend_footprint(&mut self, position: UVec2, tile: Tile) -> bool {
+        let Some(t) = self.data.get_mut(position.y as usize, position.x as usize) else {
+            return false;
+        };
+        *t = tile;
         true
     }
+    /// is the tile part of the outer walls of the room, i.e. at the border of the room or next to a Void tile.
+    /// diagonal neighbours count too, so the walls of shaped rooms have no gaps
+    pub fn is_edge_tile(&self, position: UVec2) -> bool {
+        if matches!(self.get_tile(position), None | Some(Tile::Void)) {
+            return false;
+        }
+        if position.x == 0
+            || position.y == 0
+            || position.x as usize == self.length() - 1
+            || position.y as usize == self.height() - 1
+        {
+            return true;
+        }
+        (-1..=1).any(|y| {
+            (-1..=1).any(|x| {
+                let neighbour = (position.as_ivec2() + IVec2::new(x, y)).as_uvec2();
+                self.get_tile(neighbour) == Some(&Tile::Void)
+            })
+        })
+    }
+    /// replaces every `from` tile of the room with `to`
+    pub fn replace_tiles(&mut self, from: Tile, to: Tile) {
+        self.data
+            .iter_mut()
+            .filter(|tile| **tile == from)
+            .for_each(|tile| *tile = to);
+    }
 
     #[inline(always)]
     pub fn get_area_grid(&self) -> usize {
@@ -248,16 +509,15 @@ impl Room {
         self.length() * tile_size.x as usize * self.height() * tile_size.y as usize
     }
 
+    /// turns the edge of the footprint into walls
     pub fn fill_edges(&mut self) {
-        /* match self.shape {
-            RoomShape::Rect => {
-                fill_rect(self);
-            }
-            RoomShape::Circle => {
-                fill_circle(self);
-            }
-        } */
-        fill_rect(self);
+        let edges = (0..self.height() as u32)
+            .flat_map(|y| (0..self.length() as u32).map(move |x| UVec2::new(x, y)))
+            .filter(|&position| self.is_edge_tile(position))
+            .collect::<Vec<_>>();
+        for position in edges {
+            self.set_tile(position, Tile::Wall);
+        }
     }
 
     // returns true if door was added to the room successfully, and false if internal checks gave an error
@@ -291,6 +551,13 @@ impl StructureDimensions for Room {
     fn offset_anchor_grid(&mut self, offset: IVec2) {
         self.world_pos += offset;
     }
+
+    /// Void tiles are not part of the room
+    fn is_point_inside(&self, point: (i32, i32)) -> bool {
+        self.global_to_local(IVec2::new(point.0, point.1))
+            .and_then(|local| self.get_tile(local))
+            .is_some_and(|tile| *tile != Tile::Void)
+    }
 }
 impl Structure for Room {
     fn id(&self) -> usize {
@@ -423,43 +690,26 @@ pub fn distance_between_structures<T: StructureDimensions, U: StructureDimension
     dist
 }
 
-fn fill_rect(room: &mut Room) {
-    room.data.iter_col_mut(0).for_each(|x| *x = Tile::Wall);
-    room.data
-        .iter_col_mut(room.length() - 1)
-        .for_each(|x| *x = Tile::Wall);
-    room.data.iter_row_mut(0).for_each(|x| *x = Tile::Wall);
-    room.data
-        .iter_row_mut(room.height() - 1)
-        .for_each(|x| *x = Tile::Wall);
-    //room.data.
-}
-fn _fill_circle(room: &mut Room) {
-    let room_center = Vec2::new(
-        room.length() as f32 / 2. - 0.5,
-        room.height() as f32 / 2. - 0.5,
-    );
-    for y in 0..room.data.rows() {
-        for x in 0..room.data.cols() {
-            let max_radius = ((room.length().min(room.height()) as f32 / 2.) - 1.5).max(0.);
-            if Vec2::distance(Vec2::new(x as f32, y as f32), room_center) > max_radius {
-                let tile = room.data.get_mut(y, x).unwrap();
-                *tile = Tile::Wall;
-            }
-        }
-    }
-    let tile = room
-        .data
-        .get_mut(room_center.y as usize, room_center.x as usize)
-        .unwrap();
-    *tile = Tile::Wall;
-}
-
 #[cfg(test)]
 mod tests {
     // Note this useful idiom: importing names from outer (for mod tests) scope.
     use super::*;
 
+    #[test]
+    fn test_tile_properties_order() {
+        for (i, properties) in TILE_PROPERTIES.iter().enumerate() {
+            assert!(properties.tile as usize == i);
+        }
+        assert!(Tile::Door.is_walkable() && !Tile::Door.blocks_sight());
+        assert!(!Tile::Wall.is_safe() && !Tile::Lava.is_safe() && !Tile::Pit.is_safe());
+        assert!(Tile::Carpet.is_floor() && !Tile::Water.is_floor());
+        // every symbol belongs to exactly one tile
+        for properties in TILE_PROPERTIES.iter() {
+            assert!(Tile::from_symbol(properties.symbol) == Some(properties.tile));
+        }
+        assert!(Tile::from_symbol('?').is_none());
+    }
+
     #[test]
     fn rooms_are_overlapping() {
         let room1 = Room::_new_only_spatial(10, 10, IVec2::new(0, 0));
@@ -520,6 +770,56 @@ mod tests {
         assert!(!room1.is_point_inside(point));
     }
 
+    #[test]
+    fn test_room_shapes() {
+        let shaped = |shape| {
+            let dimensions = RoomDimensions {
+                anchor: IVec2::new(-5, -5),
+                length: 10,
+                height: 10,
+            };
+            let details = RoomDetails {
+                is_main: false,
+                room_type: RoomType::Normal,
+                aesthetic_modifiers: Vec::new(),
+                keys: Vec::new(),
+                shape,
+            };
+            Room::new2(0, dimensions, details)
+        };
+
+        let mut room = shaped(RoomShape::Circle);
+        assert!(room.get_tile(UVec2::new(0, 0)) == Some(&Tile::Void));
+        assert!(!room.is_point_inside((-5, -5)));
+        assert!(room.is_point_inside((0, 0)));
+        // Void tiles are not part of the room, so they are never overwritten
+        assert!(!room.set_tile(UVec2::new(0, 0), Tile::Ground));
+        room.fill_edges();
+        // the walls follow the circle
+        assert!(room.get_tile(UVec2::new(0, 5)) == Some(&Tile::Wall));
+        assert!(room.get_tile(UVec2::new(0, 1)) == Some(&Tile::Void));
+        assert!(room.get_tile(UVec2::new(1, 1)) == Some(&Tile::Wall));
+        assert!(room.get_tile(UVec2::new(5, 5)) == Some(&Tile::Ground));
+
+        let room = shaped(RoomShape::Octagon { chamfer: 3 });
+        assert!(room.get_tile(UVec2::new(2, 0)) == Some(&Tile::Void));
+        assert!(room.get_tile(UVec2::new(3, 0)) == Some(&Tile::Ground));
+
+        let room = shaped(RoomShape::L { arm_width: 0.5 });
+        assert!(room.get_tile(UVec2::new(9, 9)) == Some(&Tile::Void));
+        assert!(room.get_tile(UVec2::new(9, 0)) == Some(&Tile::Ground));
+        assert!(room.get_tile(UVec2::new(0, 9)) == Some(&Tile::Ground));
+
+        let room = shaped(RoomShape::Cross { arm_width: 0.4 });
+        assert!(room.get_tile(UVec2::new(0, 0)) == Some(&Tile::Void));
+        assert!(room.get_tile(UVec2::new(5, 0)) == Some(&Tile::Ground));
+        assert!(room.get_tile(UVec2::new(0, 5)) == Some(&Tile::Ground));
+
+        let room = shaped(RoomShape::Polygon(vec![(0., 0.), (1., 0.), (0.5, 1.)]));
+        assert!(room.get_tile(UVec2::new(5, 1)) == Some(&Tile::Ground));
+        assert!(room.get_tile(UVec2::new(0, 9)) == Some(&Tile::Void));
+    }
+
     #[test]
     fn door_validation() {}
 
@@ -4,17 +4,28 @@ use itertools::Itertools;
 use crate::map::room::{is_overlapping, StructureDimensions};
 
 use super::{
-    generation::{MapResource, WorldgenSettings},
+    generation::{GenerationReport, MapResource, WorldgenError, WorldgenSettings},
     room::Room,
 };
 
-pub fn separate_rooms(mut map_res: ResMut<MapResource>, worldgen: Res<WorldgenSettings>) {
+/// pushes overlapping rooms away from each other. if they still overlap after 5000 iterations the generation
+/// carries on and `WorldgenError::SeparationFailed` is written into the warnings of the GenerationReport
+pub fn separate_rooms(
+    map_res: &mut MapResource,
+    worldgen: &WorldgenSettings,
+    report: &mut GenerationReport,
+) {
     let rooms = &mut map_res.map_area_mut().rooms;
     let list_of_indices = (0..rooms.len()).collect::<Box<[usize]>>();
 
+    let max_iterations = 5000;
     let mut iteration_count = 0;
     while rooms_overlap_exists(&rooms.values().collect_vec()) {
-        if iteration_count >= 5000 {
+        // the map is still usable with a few overlapping rooms, later stages hide rooms that cannot be connected
+        if iteration_count >= max_iterations {
+            let error = WorldgenError::SeparationFailed(iteration_count);
+            warn!("{error}");
+            report.warnings.push(error);
             break;
         }
 
@@ -42,6 +53,8 @@ pub fn separate_rooms(mut map_res: ResMut<MapResource>, worldgen: Res<WorldgenSe
         }
         iteration_count += 1;
     }
+    // rooms have moved, so the tile positions are stale
+    map_res.map_area_mut().rebuild_tile_index();
     println!("-- iteration count for separation stage: {iteration_count} --");
 }
 
@@ -63,3 +76,27 @@ fn rooms_overlap_exists(rooms: &[&Room]) -> bool {
     }
     return false;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_separation_failed() {
+        let mut map = MapResource::default();
+        for id in 0..2 {
+            let mut room = Room::new(id, 10, 10, IVec2::new(4, 2) * id as i32, true);
+            room.is_position_fixed = true;
+            map.map_area_mut().rooms.insert(id, room);
+        }
+        let mut report = GenerationReport::default();
+        separate_rooms(&mut map, &WorldgenSettings::default(), &mut report);
+        assert!(report.warnings == vec![WorldgenError::SeparationFailed(5000)]);
+
+        // rooms that can move apart are separated without a warning
+        map.map_area_mut().rooms.get_mut(&1).unwrap().is_position_fixed = false;
+        let mut report = GenerationReport::default();
+        separate_rooms(&mut map, &WorldgenSettings::default(), &mut report);
+        assert!(report.warnings.is_empty());
+    }
+}
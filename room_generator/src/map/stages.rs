@@ -0,0 +1,205 @@
+use std::sync::Arc;
+
+use bevy::prelude::*;
+
+use super::{
+    connecting, export,
+    generation::{
+        self, GenerationReport, MapResource, WorldgenError, WorldgenRng, WorldgenSettings,
+    },
+    graphing, hubs,
+    pipeline::PostprocessPipeline,
+    presets::RoomPresetResource,
+    progression, separation, world_tiles,
+};
+
+/// one step of the map generation. every stage works on the map of the MapResource,
+/// a stage that fails stops the generation and the previous map stays on screen.
+///
+/// custom stages are added with `MapPlugin::with_stage_before` and `MapPlugin::with_stage_after`
+pub trait GenerationStage: Send + Sync + 'static {
+    /// other stages are inserted relative to this name
+    fn name(&self) -> &str;
+    fn run(&self, context: &mut StageContext) -> Result<(), WorldgenError>;
+}
+
+/// everything a stage can read and change
+pub struct StageContext<'a> {
+    pub map: &'a mut MapResource,
+    pub rng: &'a mut WorldgenRng,
+    pub worldgen: &'a WorldgenSettings,
+    pub presets: &'a RoomPresetResource,
+    pub report: &'a mut GenerationReport,
+}
+
+/// a stage that comes with the plugin. they are plain functions, so a struct for each of them is not needed
+#[derive(Clone, Copy)]
+pub struct BuiltinStage {
+    name: &'static str,
+    run: fn(&mut StageContext) -> Result<(), WorldgenError>,
+}
+impl GenerationStage for BuiltinStage {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn run(&self, context: &mut StageContext) -> Result<(), WorldgenError> {
+        (self.run)(context)
+    }
+}
+
+/// the built-in stages that run before the postprocess pipeline
+#[rustfmt::skip]
+const STAGES_BEFORE_POSTPROCESS: [BuiltinStage; 11] = [
+    BuiltinStage { name: "generate_rooms", run: |c| generation::generate_rooms(c.map, c.worldgen, c.rng, c.presets) },
+    BuiltinStage { name: "determine_main_rooms", run: |c| { generation::determine_main_rooms(c.map); Ok(()) } },
+    BuiltinStage { name: "separate_rooms", run: |c| { separation::separate_rooms(c.map, c.worldgen, c.report); Ok(()) } },
+    BuiltinStage { name: "get_triangulation", run: |c| { graphing::get_triangulation(c.map); Ok(()) } },
+    BuiltinStage { name: "make_graphs", run: |c| graphing::make_graphs(c.map, c.worldgen) },
+    BuiltinStage { name: "export_graphs", run: |c| { export::export_graphs(c.map, c.worldgen); Ok(()) } },
+    BuiltinStage { name: "connect_rooms", run: |c| connecting::connect_rooms(c.map, c.worldgen) },
+    BuiltinStage { name: "merge_hallways", run: |c| connecting::merge_hallways(c.map, c.worldgen) },
+    BuiltinStage { name: "place_hub_rooms", run: |c| hubs::place_hub_rooms(c.map, c.worldgen, c.presets) },
+    BuiltinStage { name: "remake_graphs", run: |c| graphing::remake_graphs(c.map) },
+    BuiltinStage { name: "assign_locks_and_keys", run: |c| progression::assign_locks_and_keys(c.map, c.worldgen, c.rng) },
+];
+
+/// the built-in stages that run after the postprocess pipeline
+#[rustfmt::skip]
+const STAGES_AFTER_POSTPROCESS: [BuiltinStage; 2] = [
+    BuiltinStage { name: "build_world_tiles", run: |c| { world_tiles::build_world_tiles(c.map, c.worldgen); Ok(()) } },
+    BuiltinStage { name: "export_world_tiles", run: |c| { export::export_world_tiles(c.map, c.worldgen); Ok(()) } },
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StagePosition {
+    Before(String),
+    After(String),
+}
+
+/// stages added by users of the plugin, in the order they were added
+#[derive(Clone, Default, Resource)]
+pub struct CustomStages(pub Vec<(StagePosition, Arc<dyn GenerationStage>)>);
+
+/// every stage in the order it runs. the postprocess passes are stages too, so custom stages
+/// can also be placed around them (e.g. after "carve_doors").
+/// a custom stage whose anchor does not exist (a disabled pass for example) is skipped. if the anchor
+/// runs more than once, the custom stage is only placed around the first one
+pub fn stage_order(
+    custom: &CustomStages,
+    pipeline: &PostprocessPipeline,
+) -> Vec<Arc<dyn GenerationStage>> {
+    let builtin = STAGES_BEFORE_POSTPROCESS
+        .iter()
+        .map(|s| Arc::new(*s) as Arc<dyn GenerationStage>)
+        .chain(pipeline.stages())
+        .chain(
+            STAGES_AFTER_POSTPROCESS
+                .iter()
+                .map(|s| Arc::new(*s) as Arc<dyn GenerationStage>),
+        )
+        .collect::<Vec<_>>();
+
+    let mut placed = vec![false; custom.0.len()];
+    let mut place_custom = |position: StagePosition, order: &mut Vec<Arc<dyn GenerationStage>>| {
+        for (i, (p, stage)) in custom.0.iter().enumerate() {
+            if *p == position && !placed[i] {
+                placed[i] = true;
+                order.push(stage.clone());
+            }
+        }
+    };
+
+    let mut order = vec![];
+    for stage in builtin.iter() {
+        place_custom(StagePosition::Before(stage.name().into()), &mut order);
+        order.push(stage.clone());
+        place_custom(StagePosition::After(stage.name().into()), &mut order);
+    }
+
+    for ((position, stage), placed) in custom.0.iter().zip(placed) {
+        if !placed {
+            let (StagePosition::Before(anchor) | StagePosition::After(anchor)) = position;
+            warn!(
+                "there is no generation stage called {}, {} is skipped",
+                anchor,
+                stage.name()
+            );
+        }
+    }
+    order
+}
+
+/// runs every stage in order and stops at the first one that fails
+pub fn run_generation_stages(
+    custom: Res<CustomStages>,
+    pipeline: Res<PostprocessPipeline>,
+    mut map: ResMut<MapResource>,
+    mut rng: ResMut<WorldgenRng>,
+    worldgen: Res<WorldgenSettings>,
+    presets: Res<RoomPresetResource>,
+    mut report: ResMut<GenerationReport>,
+) -> Result<(), WorldgenError> {
+    let mut context = StageContext {
+        map: &mut map,
+        rng: &mut rng,
+        worldgen: &worldgen,
+        presets: &presets,
+        report: &mut report,
+    };
+    for stage in stage_order(&custom, &pipeline) {
+        stage.run(&mut context)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NamedStage(&'static str);
+    impl GenerationStage for NamedStage {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn run(&self, _context: &mut StageContext) -> Result<(), WorldgenError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_stage_order() {
+        let stage = |position: StagePosition, name: &'static str| {
+            (
+                position,
+                Arc::new(NamedStage(name)) as Arc<dyn GenerationStage>,
+            )
+        };
+        let custom = CustomStages(vec![
+            stage(StagePosition::After("carve_doors".into()), "treasure"),
+            stage(StagePosition::After("carve_doors".into()), "traps"),
+            stage(StagePosition::Before("generate_rooms".into()), "first"),
+            stage(StagePosition::Before("does_not_exist".into()), "last"),
+        ]);
+        let order = stage_order(&custom, &PostprocessPipeline::default());
+        let names = order.iter().map(|s| s.name()).collect::<Vec<_>>();
+
+        assert!(names[0] == "first");
+        assert!(names[1] == "generate_rooms");
+        let doors = names.iter().position(|n| *n == "carve_doors").unwrap();
+        assert!(names[doors + 1] == "treasure");
+        assert!(names[doors + 2] == "traps");
+        assert!(names[doors + 3] == "door_aesthetics");
+        assert!(!names.contains(&"last"));
+        assert!(names.len() == 11 + 8 + 2 + 3);
+
+        // a pass that runs twice does not duplicate the stages around it
+        let mut pipeline = PostprocessPipeline::default();
+        pipeline.passes.push(pipeline.passes[5].clone());
+        let order = stage_order(&custom, &pipeline);
+        let names = order.iter().map(|s| s.name()).collect::<Vec<_>>();
+        assert!(names.iter().filter(|n| **n == "treasure").count() == 1);
+        assert!(names.len() == 11 + 9 + 2 + 3);
+    }
+}
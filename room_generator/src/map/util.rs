@@ -26,6 +26,12 @@ pub fn maybe_flip_bl_tr<'a>(
     }
 }
 
+/// key for an undirected edge between two rooms, so (a, b) and (b, a) are the same
+#[inline(always)]
+pub fn edge_key(a: usize, b: usize) -> (usize, usize) {
+    (a.min(b), a.max(b))
+}
+
 pub fn find_out_door_orientation(overlap_x: i32, overlap_y: i32) -> DoorOrientation {
     let overlap = overlap_x.max(overlap_y);
     let door_orientation = if overlap == overlap_x {
@@ -67,6 +73,60 @@ pub fn common_edge(
     (overlap_x, overlap_y)
 }
 
+/// the longest run of tiles along the common edge of two rooms where the sides that face each other are part of the
+/// footprints of both rooms. for rooms without a shape this is the whole common edge. the rooms do not have to touch.
+/// returns the start and end (exclusive) of the run, on the y axis if the rooms are next to each other (Vertical)
+/// and on the x axis if they are above each other (Horziontal). the run is empty if the footprints never face each other
+pub fn footprint_edge(
+    room1: &impl StructureDimensions,
+    room2: &impl StructureDimensions,
+    orientation: DoorOrientation,
+) -> (i32, i32) {
+    let (_, bl_room, tr_room) = match orientation {
+        DoorOrientation::Vertical => {
+            maybe_flip_bl_tr(room1.anchor_grid().x < room2.anchor_grid().x, room1, room2)
+        }
+        DoorOrientation::Horziontal => {
+            maybe_flip_bl_tr(room1.anchor_grid().y < room2.anchor_grid().y, room1, room2)
+        }
+    };
+    let (start, end) = match orientation {
+        DoorOrientation::Vertical => (
+            bl_room.anchor_grid().y.max(tr_room.anchor_grid().y),
+            bl_room.anchor_grid_end().y.min(tr_room.anchor_grid_end().y),
+        ),
+        DoorOrientation::Horziontal => (
+            bl_room.anchor_grid().x.max(tr_room.anchor_grid().x),
+            bl_room.anchor_grid_end().x.min(tr_room.anchor_grid_end().x),
+        ),
+    };
+    // the tiles of both rooms that face each other at position i of the edge
+    let facing = |i: i32| match orientation {
+        DoorOrientation::Vertical => (
+            (bl_room.anchor_grid_end().x - 1, i),
+            (tr_room.anchor_grid().x, i),
+        ),
+        DoorOrientation::Horziontal => (
+            (i, bl_room.anchor_grid_end().y - 1),
+            (i, tr_room.anchor_grid().y),
+        ),
+    };
+
+    let mut longest = (start, start);
+    let mut run_start = start;
+    for i in start..end {
+        let (point1, point2) = facing(i);
+        if !bl_room.is_point_inside(point1) || !tr_room.is_point_inside(point2) {
+            run_start = i + 1;
+            continue;
+        }
+        if i + 1 - run_start > longest.1 - longest.0 {
+            longest = (run_start, i + 1);
+        }
+    }
+    longest
+}
+
 // try to find some type of source for this but ngl i came up with this myself
 pub fn line_overlap(a1: i32, a2: i32, b1: i32, b2: i32) -> i32 {
     let start = a1.max(b1);
@@ -1,9 +1,10 @@
-use super::generation::{MapArea, MapResource, WorldgenSettings};
+use super::generation::{GenerationReport, MapArea, MapResource, WorldgenSettings};
 use super::room::StructureDimensions;
 use super::room::{Room, Tile};
 use crate::assets::TextureAtlases;
 use bevy::prelude::*;
 use bevy::sprite::Anchor;
+use bevy_egui::{egui, EguiContexts};
 use bevy_text_mode::{TextModeSpriteSheetBundle, TextModeTextureAtlasSprite};
 use itertools::Itertools;
 
@@ -14,6 +15,7 @@ pub struct WorldgenGizmos {
     pub show_triangulation: bool,
     pub show_middle_of_rooms: bool,
     pub show_doors: bool,
+    pub show_hallways: bool,
 }
 
 impl Default for WorldgenGizmos {
@@ -23,6 +25,7 @@ impl Default for WorldgenGizmos {
             show_triangulation: false,
             show_middle_of_rooms: true,
             show_doors: true,
+            show_hallways: true,
         }
     }
 }
@@ -34,38 +37,31 @@ pub fn spawn_rooms_visuals(
     worldgen: Res<WorldgenSettings>,
 ) {
     let map_area = map_res.map_area_mut();
+    // with a unified world grid the tiles are drawn from it, the room entities only keep their data
+    let use_world_tiles = map_area.world_tiles.is_some();
     for room in map_area.rooms.values() {
         if !room.is_visible {
             continue;
         }
 
         let mut children: Vec<Entity> = Vec::new();
-        for y in 0..room.height() {
-            for x in 0..room.length() {
-                // safety: x and y exist for sure
-                let tile = room.get_tile(UVec2::new(x as u32, y as u32)).unwrap();
-                let color = match *tile {
-                    Tile::Ground => Color::hex("#222222").unwrap(),
-                    Tile::Wall => Color::hex("#777777").unwrap(),
-                };
-
-                let tile_entity = commands.spawn(TextModeSpriteSheetBundle {
-                    sprite: TextModeTextureAtlasSprite {
-                        index: 0,
-                        fg: color,
-                        bg: color,
-                        anchor: Anchor::BottomLeft,
-                        ..default()
-                    },
-                    texture_atlas: atlases.basic_tile.clone(),
-                    transform: Transform::from_translation(Vec3::new(
-                        x as f32 * worldgen.tile_size.x as f32,
-                        y as f32 * worldgen.tile_size.y as f32,
-                        -10.,
-                    )),
-                    ..default()
-                });
-                children.push(tile_entity.id());
+        if !use_world_tiles {
+            for y in 0..room.height() {
+                for x in 0..room.length() {
+                    // safety: x and y exist for sure
+                    let tile = room.get_tile(UVec2::new(x as u32, y as u32)).unwrap();
+                    if *tile == Tile::Void {
+                        continue;
+                    }
+                    let position = IVec2::new(x as i32, y as i32);
+                    children.push(spawn_tile(
+                        &mut commands,
+                        &atlases,
+                        &worldgen,
+                        *tile,
+                        position,
+                    ));
+                }
             }
         }
         let spatial_bundle = SpatialBundle::from_transform(Transform::from_translation(
@@ -81,7 +77,64 @@ pub fn spawn_rooms_visuals(
             ))
             .push_children(&children);
     }
-    commands.spawn(map_res.map_area_mut().clone());
+
+    let mut children: Vec<Entity> = Vec::new();
+    let mut anchor = IVec2::ZERO;
+    if let Some(world_tiles) = &map_area.world_tiles {
+        anchor = world_tiles.anchor();
+        for position in world_tiles.positions() {
+            if let Some(tile) = world_tiles.get(position) {
+                children.push(spawn_tile(
+                    &mut commands,
+                    &atlases,
+                    &worldgen,
+                    tile,
+                    position - anchor,
+                ));
+            }
+        }
+    }
+    commands
+        .spawn((
+            SpatialBundle::from_transform(Transform::from_translation(
+                (anchor * worldgen.tile_size.as_ivec2())
+                    .as_vec2()
+                    .extend(-10.),
+            )),
+            map_area.clone(),
+            Name::new("Map area"),
+        ))
+        .push_children(&children);
+}
+
+/// spawns the sprite of a single tile. the position is relative to the parent entity
+fn spawn_tile(
+    commands: &mut Commands,
+    atlases: &TextureAtlases,
+    worldgen: &WorldgenSettings,
+    tile: Tile,
+    position: IVec2,
+) -> Entity {
+    let color = Color::hex(tile.properties().color).unwrap();
+
+    commands
+        .spawn(TextModeSpriteSheetBundle {
+            sprite: TextModeTextureAtlasSprite {
+                index: 0,
+                fg: color,
+                bg: color,
+                anchor: Anchor::BottomLeft,
+                ..default()
+            },
+            texture_atlas: atlases.basic_tile.clone(),
+            transform: Transform::from_translation(Vec3::new(
+                position.x as f32 * worldgen.tile_size.x as f32,
+                position.y as f32 * worldgen.tile_size.y as f32,
+                -10.,
+            )),
+            ..default()
+        })
+        .id()
 }
 
 pub fn gizmo_graph_edges(
@@ -147,7 +200,9 @@ pub fn gizmo_room_middle_circle(
         return;
     }
     for room in room_query.iter() {
-        let color = if room.details.is_main {
+        let color = if room.is_hub() {
+            Color::PURPLE
+        } else if room.details.is_main {
             if map
                 .map_area()
                 .graph
@@ -165,4 +220,90 @@ pub fn gizmo_room_middle_circle(
         };
         gizmos.circle_2d(room.center_world(worldgen.tile_size), 5., color);
     }
-}
\ No newline at end of file
+}
+
+/// outlines every hallway segment and connects the segments that were built for the same connection
+pub fn gizmo_hallways(
+    mut gizmos: Gizmos,
+    worldgen: Res<WorldgenSettings>,
+    map_area_query: Query<&MapArea>,
+    gizmo_settings: Res<WorldgenGizmos>,
+) {
+    if !gizmo_settings.show_hallways {
+        return;
+    }
+    let tile_size = worldgen.tile_size.as_vec2();
+    for map_area in map_area_query.iter() {
+        let hallways = map_area.hallways();
+        for hallway in hallways.iter().filter(|h| h.is_visible) {
+            gizmos.rect_2d(
+                hallway.center_world(worldgen.tile_size),
+                0.,
+                Vec2::new(hallway.length() as f32, hallway.height() as f32) * tile_size,
+                Color::YELLOW,
+            );
+        }
+        for connection in map_area
+            .rooms
+            .values()
+            .filter_map(|r| r.hallway_details())
+            .flat_map(|details| details.segments.iter().map(|s| s.connection))
+            .unique()
+        {
+            gizmos.linestrip_2d(
+                map_area
+                    .hallways_of_connection(connection.0, connection.1)
+                    .iter()
+                    .map(|h| h.center_world(worldgen.tile_size)),
+                Color::GOLD,
+            );
+        }
+    }
+}
+
+/// outlines every door, locked doors in red, boss gates in fuchsia and drops in yellow
+pub fn gizmo_doors(
+    mut gizmos: Gizmos,
+    worldgen: Res<WorldgenSettings>,
+    map_area_query: Query<&MapArea>,
+    gizmo_settings: Res<WorldgenGizmos>,
+) {
+    if !gizmo_settings.show_doors {
+        return;
+    }
+    for map_area in map_area_query.iter() {
+        for door in map_area.doors.iter() {
+            let color = if door.is_locked {
+                Color::RED
+            } else if door.is_boss_gate {
+                Color::FUCHSIA
+            } else if door.is_one_way {
+                Color::YELLOW
+            } else {
+                Color::CYAN
+            };
+            gizmos.rect_2d(
+                door.center_world(worldgen.tile_size),
+                0.,
+                door.size().as_vec2() * worldgen.tile_size.as_vec2(),
+                color,
+            );
+        }
+    }
+}
+
+/// small window that stays open while the last generation failed or had warnings
+pub fn show_generation_error(mut contexts: EguiContexts, report: Res<GenerationReport>) {
+    if let Some(error) = &report.error {
+        egui::Window::new("map generation failed").show(contexts.ctx_mut(), |ui| {
+            ui.colored_label(egui::Color32::LIGHT_RED, error.to_string());
+            ui.label("the previous map is still shown. change the worldgen settings to try again");
+        });
+    } else if !report.warnings.is_empty() {
+        egui::Window::new("map generated with problems").show(contexts.ctx_mut(), |ui| {
+            for warning in report.warnings.iter() {
+                ui.colored_label(egui::Color32::YELLOW, warning.to_string());
+            }
+        });
+    }
+}
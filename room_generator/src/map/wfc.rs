@@ -0,0 +1,367 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use bevy::{prelude::*, utils::HashMap};
+use grid::Grid;
+
+use super::room::Tile;
+
+/// offsets of the four neighbours. the opposite of direction `d` is `(d + 2) % 4`
+const NEIGHBOURS: [IVec2; 4] = [IVec2::X, IVec2::Y, IVec2::NEG_X, IVec2::NEG_Y];
+
+/// the overlapping model of wave function collapse (https://github.com/mxgmn/WaveFunctionCollapse).
+/// every n*n pattern of a sample becomes a pattern of the model. the output is made of patterns that overlap,
+/// so every n*n window of the output also appears somewhere in the sample
+#[derive(Debug, Clone)]
+pub struct OverlappingModel {
+    n: usize,
+    patterns: Vec<Vec<Tile>>, // n*n tiles each, row by row from the bottom
+    weights: Vec<f64>,        // how often each pattern appears in the sample
+    // for every direction and pattern: the patterns that can be next to it in that direction, as a bitset
+    propagator: [Vec<Vec<u64>>; 4],
+    words: usize, // length of a bitset
+}
+
+impl OverlappingModel {
+    /// `sample` is indexed with [y][x], y goes up. with `symmetry` the rotated and mirrored patterns are used too.
+    /// returns None if the sample is smaller than a pattern or has rows of different lengths
+    pub fn from_sample(sample: &[Vec<Tile>], n: usize, symmetry: bool) -> Option<Self> {
+        let height = sample.len();
+        let width = sample.first()?.len();
+        if n == 0 || width < n || height < n || sample.iter().any(|row| row.len() != width) {
+            return None;
+        }
+
+        let mut indices = HashMap::new();
+        let mut patterns = vec![];
+        let mut weights = vec![];
+        for y in 0..=height - n {
+            for x in 0..=width - n {
+                let pattern = (0..n * n)
+                    .map(|i| sample[y + i / n][x + i % n])
+                    .collect::<Vec<_>>();
+                let variants = match symmetry {
+                    true => symmetries(&pattern, n),
+                    false => vec![pattern],
+                };
+                for variant in variants {
+                    let index = *indices.entry(variant.clone()).or_insert_with(|| {
+                        patterns.push(variant);
+                        weights.push(0.);
+                        patterns.len() - 1
+                    });
+                    weights[index] += 1.;
+                }
+            }
+        }
+
+        let words = patterns.len().div_ceil(64);
+        let propagator = std::array::from_fn(|d| {
+            let offset = NEIGHBOURS[d];
+            patterns
+                .iter()
+                .map(|a| {
+                    let mut compatible = vec![0u64; words];
+                    for (q, b) in patterns.iter().enumerate() {
+                        if agrees(a, b, offset, n) {
+                            compatible[q / 64] |= 1 << (q % 64);
+                        }
+                    }
+                    compatible
+                })
+                .collect()
+        });
+
+        Some(Self {
+            n,
+            patterns,
+            weights,
+            propagator,
+            words,
+        })
+    }
+
+    pub fn pattern_count(&self) -> usize {
+        self.patterns.len()
+    }
+
+    /// true if the tile appears in any pattern
+    pub fn contains(&self, tile: Tile) -> bool {
+        self.patterns.iter().any(|p| p.contains(&tile))
+    }
+
+    /// generates a width * height grid. `fixed` tiles are kept as they are.
+    /// returns None on a contradiction, a different rng might still find a solution
+    pub fn run(
+        &self,
+        width: usize,
+        height: usize,
+        fixed: &[(UVec2, Tile)],
+        rng: &mut fastrand::Rng,
+    ) -> Option<Grid<Tile>> {
+        if width < self.n || height < self.n {
+            return None;
+        }
+        let mut wave = Wave::new(self, width - self.n + 1, height - self.n + 1);
+
+        // patterns that do not have the fixed tile at the right spot are banned from every cell that covers it
+        let mut changed = vec![];
+        for &(position, tile) in fixed.iter() {
+            let (x, y) = (position.x as usize, position.y as usize);
+            if x >= width || y >= height {
+                continue;
+            }
+            for cy in y.saturating_sub(self.n - 1)..=y.min(wave.height - 1) {
+                for cx in x.saturating_sub(self.n - 1)..=x.min(wave.width - 1) {
+                    let offset = (y - cy) * self.n + (x - cx);
+                    let cell = wave.index(cx, cy);
+                    for p in 0..self.patterns.len() {
+                        if self.patterns[p][offset] != tile && wave.ban(cell, p) {
+                            changed.push(cell);
+                        }
+                    }
+                }
+            }
+        }
+        self.propagate(&mut wave, changed)?;
+
+        // collapse the cell with the fewest options left, until every cell has one pattern
+        let mut queue = BinaryHeap::new();
+        for cell in 0..wave.counts.len() {
+            queue.push(Reverse((wave.counts[cell], rng.u32(..), cell)));
+        }
+        while let Some(Reverse((count, _, cell))) = queue.pop() {
+            // entries become stale when the cell changes. the fresh entry is in the queue too
+            if count != wave.counts[cell] || count <= 1 {
+                continue;
+            }
+            let pattern = self.choose(&wave, cell, rng);
+            let mut changed = vec![];
+            for p in 0..self.patterns.len() {
+                if p != pattern && wave.ban(cell, p) {
+                    changed.push(cell);
+                }
+            }
+            for cell in self.propagate(&mut wave, changed)? {
+                queue.push(Reverse((wave.counts[cell], rng.u32(..), cell)));
+            }
+        }
+
+        let mut output = Grid::new(height, width);
+        for y in 0..height {
+            for x in 0..width {
+                let (cx, cy) = (x.min(wave.width - 1), y.min(wave.height - 1));
+                let pattern = wave.patterns(wave.index(cx, cy)).next()?;
+                output[y][x] = self.patterns[pattern][(y - cy) * self.n + (x - cx)];
+            }
+        }
+        Some(output)
+    }
+
+    /// a random pattern of the cell, weighted by how often it appears in the sample
+    fn choose(&self, wave: &Wave, cell: usize, rng: &mut fastrand::Rng) -> usize {
+        let options = wave.patterns(cell).collect::<Vec<_>>();
+        let total = options.iter().map(|&p| self.weights[p]).sum::<f64>();
+        let mut pick = rng.f64() * total;
+        for &p in options.iter() {
+            pick -= self.weights[p];
+            if pick <= 0. {
+                return p;
+            }
+        }
+        *options.last().unwrap()
+    }
+
+    /// removes patterns of the neighbours that do not fit anymore, until nothing changes.
+    /// returns every cell that changed, or None if a cell has no pattern left
+    fn propagate(&self, wave: &mut Wave, mut stack: Vec<usize>) -> Option<Vec<usize>> {
+        let mut changed = stack.clone();
+        while let Some(cell) = stack.pop() {
+            if wave.counts[cell] == 0 {
+                return None;
+            }
+            let (x, y) = (cell % wave.width, cell / wave.width);
+            for (d, offset) in NEIGHBOURS.iter().enumerate() {
+                let (nx, ny) = (x as i32 + offset.x, y as i32 + offset.y);
+                if nx < 0 || ny < 0 || nx >= wave.width as i32 || ny >= wave.height as i32 {
+                    continue;
+                }
+                let neighbour = wave.index(nx as usize, ny as usize);
+
+                let mut allowed = vec![0u64; self.words];
+                for p in wave.patterns(cell) {
+                    for (word, compatible) in allowed.iter_mut().zip(self.propagator[d][p].iter()) {
+                        *word |= compatible;
+                    }
+                }
+                if wave.restrict(neighbour, &allowed) {
+                    if wave.counts[neighbour] == 0 {
+                        return None;
+                    }
+                    stack.push(neighbour);
+                    changed.push(neighbour);
+                }
+            }
+        }
+        Some(changed)
+    }
+}
+
+/// the patterns that are still possible for every cell
+struct Wave {
+    width: usize,
+    height: usize,
+    words: usize,
+    bits: Vec<u64>,
+    counts: Vec<usize>,
+}
+impl Wave {
+    fn new(model: &OverlappingModel, width: usize, height: usize) -> Self {
+        let mut cell = vec![0u64; model.words];
+        for p in 0..model.patterns.len() {
+            cell[p / 64] |= 1 << (p % 64);
+        }
+        Self {
+            width,
+            height,
+            words: model.words,
+            bits: cell.repeat(width * height),
+            counts: vec![model.patterns.len(); width * height],
+        }
+    }
+
+    fn index(&self, x: usize, y: usize) -> usize {
+        y * self.width + x
+    }
+
+    fn patterns(&self, cell: usize) -> impl Iterator<Item = usize> + '_ {
+        let bits = &self.bits[cell * self.words..(cell + 1) * self.words];
+        (0..self.words * 64).filter(move |p| bits[p / 64] & (1 << (p % 64)) != 0)
+    }
+
+    /// returns true if the pattern was possible before
+    fn ban(&mut self, cell: usize, pattern: usize) -> bool {
+        let word = &mut self.bits[cell * self.words + pattern / 64];
+        let mask = 1 << (pattern % 64);
+        if *word & mask == 0 {
+            return false;
+        }
+        *word &= !mask;
+        self.counts[cell] -= 1;
+        true
+    }
+
+    /// keeps only the allowed patterns. returns true if the cell changed
+    fn restrict(&mut self, cell: usize, allowed: &[u64]) -> bool {
+        let bits = &mut self.bits[cell * self.words..(cell + 1) * self.words];
+        let mut changed = false;
+        for (word, allowed) in bits.iter_mut().zip(allowed.iter()) {
+            if *word & !allowed != 0 {
+                *word &= allowed;
+                changed = true;
+            }
+        }
+        if changed {
+            self.counts[cell] = bits.iter().map(|w| w.count_ones() as usize).sum();
+        }
+        changed
+    }
+}
+
+/// true if `b` can be placed at `offset` from `a`, i.e. the tiles where they overlap are the same
+fn agrees(a: &[Tile], b: &[Tile], offset: IVec2, n: usize) -> bool {
+    let n = n as i32;
+    for y in offset.y.max(0)..(n + offset.y).min(n) {
+        for x in offset.x.max(0)..(n + offset.x).min(n) {
+            let b_x = x - offset.x;
+            let b_y = y - offset.y;
+            if a[(y * n + x) as usize] != b[(b_y * n + b_x) as usize] {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// the pattern, rotated four times, and the same again mirrored
+fn symmetries(pattern: &[Tile], n: usize) -> Vec<Vec<Tile>> {
+    let rotate = |p: &[Tile]| {
+        (0..n * n)
+            .map(|i| p[(n - 1 - i % n) * n + i / n])
+            .collect::<Vec<_>>()
+    };
+    let mirror = |p: &[Tile]| {
+        (0..n * n)
+            .map(|i| p[(i / n) * n + n - 1 - i % n])
+            .collect::<Vec<_>>()
+    };
+    let mut variants = vec![pattern.to_vec()];
+    for _ in 0..3 {
+        variants.push(rotate(variants.last().unwrap()));
+    }
+    let mirrored = variants.iter().map(|p| mirror(p)).collect::<Vec<_>>();
+    variants.extend(mirrored);
+    variants
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(rows: &[&str]) -> Vec<Vec<Tile>> {
+        rows.iter()
+            .rev()
+            .map(|row| row.chars().map(|c| Tile::from_symbol(c).unwrap()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_output_is_made_of_sample_patterns() {
+        #[rustfmt::skip]
+        let sample = sample(&[
+            "........",
+            ".##..##.",
+            ".##..##.",
+            "........",
+            "........",
+            ".##..##.",
+            ".##..##.",
+            "........",
+        ]);
+        let model = OverlappingModel::from_sample(&sample, 3, false).unwrap();
+        let fixed = [
+            (UVec2::new(0, 0), Tile::Ground),
+            (UVec2::new(5, 5), Tile::Wall),
+        ];
+        let mut rng = fastrand::Rng::with_seed(5);
+        let output = (0..10)
+            .find_map(|_| model.run(20, 15, &fixed, &mut rng))
+            .unwrap();
+
+        assert!(output.rows() == 15 && output.cols() == 20);
+        assert!(output[0][0] == Tile::Ground);
+        assert!(output[5][5] == Tile::Wall);
+        // every 3x3 window of the output is a pattern of the sample
+        for y in 0..=12 {
+            for x in 0..=17 {
+                let window = (0..9)
+                    .map(|i| output[y + i / 3][x + i % 3])
+                    .collect::<Vec<_>>();
+                assert!(model.patterns.contains(&window));
+            }
+        }
+    }
+
+    #[test]
+    fn test_impossible_fixed_tiles() {
+        let sample = sample(&["..#", ".##", "..."]);
+        let model = OverlappingModel::from_sample(&sample, 2, true).unwrap();
+        // the rotations and mirrors are separate patterns
+        assert!(model.pattern_count() > 4);
+        assert!(!model.contains(Tile::Lava));
+        let mut rng = fastrand::Rng::with_seed(1);
+        assert!(model
+            .run(6, 6, &[(UVec2::new(2, 2), Tile::Lava)], &mut rng)
+            .is_none());
+        assert!(OverlappingModel::from_sample(&sample, 4, false).is_none());
+    }
+}
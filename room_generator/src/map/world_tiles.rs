@@ -0,0 +1,191 @@
+use bevy::prelude::*;
+use grid::Grid;
+use itertools::Itertools;
+
+use super::{
+    generation::{MapArea, MapResource, WorldgenSettings},
+    room::{StructureDimensions, Tile},
+};
+
+/// every visible room merged into one grid in global coordinates.
+/// rendering, collision and exports can use this instead of going through every room, the per room grids stay untouched
+#[derive(Debug, Clone)]
+pub struct WorldTiles {
+    anchor: IVec2,
+    tiles: Grid<Option<Tile>>, // None where there is no room
+}
+impl WorldTiles {
+    /// merges the rooms of the map area. the rules for tiles of different rooms are:
+    /// - if rooms overlap, Wall wins over Ground
+    /// - where the walls of two rooms touch, the wall of the room with the higher id turns into the floor behind it,
+    ///   as long as the tile behind it is a floor tile. so the two wall rows become a single wall
+    /// - a wall next to the floor of another room (i.e. a door that was carved on one side only) stays a wall
+    pub fn from_map_area(map_area: &MapArea) -> Option<Self> {
+        let rooms = map_area
+            .rooms
+            .values()
+            .filter(|r| r.is_visible)
+            .sorted_by_key(|r| r.id())
+            .collect_vec();
+        let anchor = rooms.iter().map(|r| r.anchor_grid()).reduce(IVec2::min)?;
+        let end = rooms
+            .iter()
+            .map(|r| r.anchor_grid_end())
+            .reduce(IVec2::max)?;
+        let size = (end - anchor).as_uvec2();
+
+        let mut tiles = Grid::new(size.y as usize, size.x as usize);
+        let mut owners: Grid<Option<usize>> = Grid::new(size.y as usize, size.x as usize);
+        for room in rooms.iter() {
+            for y in 0..room.height() as u32 {
+                for x in 0..room.length() as u32 {
+                    let local = UVec2::new(x, y);
+                    let global = (room.local_to_global(local) - anchor).as_uvec2();
+                    let tile = *room.get_tile(local).unwrap();
+                    if tile == Tile::Void {
+                        continue;
+                    }
+                    let cell = tiles.get_mut(global.y as usize, global.x as usize).unwrap();
+                    if !matches!(*cell, Some(Tile::Wall)) {
+                        *cell = Some(tile);
+                        *owners
+                            .get_mut(global.y as usize, global.x as usize)
+                            .unwrap() = Some(room.id());
+                    }
+                }
+            }
+        }
+
+        let mut world_tiles = Self { anchor, tiles };
+        let double_walls = world_tiles.double_walls(&owners);
+        for (position, floor) in double_walls {
+            world_tiles.set(position, floor);
+        }
+        Some(world_tiles)
+    }
+
+    /// wall tiles that touch a wall of another room with a lower id and have a floor tile of their own room behind them,
+    /// together with that floor tile
+    fn double_walls(&self, owners: &Grid<Option<usize>>) -> Vec<(IVec2, Tile)> {
+        let owner = |point: IVec2| {
+            let local = self.to_local(point)?;
+            *owners.get(local.y as usize, local.x as usize)?
+        };
+        let mut double_walls = vec![];
+        for point in self.positions() {
+            let Some(room_id) = owner(point) else {
+                continue;
+            };
+            if self.get(point) != Some(Tile::Wall) {
+                continue;
+            }
+            let floor = [IVec2::X, IVec2::Y, IVec2::NEG_X, IVec2::NEG_Y]
+                .into_iter()
+                .find_map(|direction| {
+                    let other = point + direction;
+                    let behind = point - direction;
+                    let is_double = owner(other).is_some_and(|other_id| other_id < room_id)
+                        && self.get(other) == Some(Tile::Wall)
+                        && owner(behind) == Some(room_id);
+                    self.get(behind).filter(|tile| is_double && tile.is_floor())
+                });
+            if let Some(floor) = floor {
+                double_walls.push((point, floor));
+            }
+        }
+        double_walls
+    }
+
+    /// the bottom left tile of the grid in global coordinates
+    pub fn anchor(&self) -> IVec2 {
+        self.anchor
+    }
+
+    pub fn size(&self) -> UVec2 {
+        UVec2::new(self.tiles.cols() as u32, self.tiles.rows() as u32)
+    }
+
+    /// the tile at a global position. None if there is no room there
+    pub fn get(&self, point: IVec2) -> Option<Tile> {
+        let local = self.to_local(point)?;
+        *self.tiles.get(local.y as usize, local.x as usize)?
+    }
+
+    pub fn is_walkable(&self, point: IVec2) -> bool {
+        self.get(point).is_some_and(|tile| tile.is_walkable())
+    }
+
+    fn set(&mut self, point: IVec2, tile: Tile) {
+        if let Some(local) = self.to_local(point) {
+            if let Some(cell) = self.tiles.get_mut(local.y as usize, local.x as usize) {
+                *cell = Some(tile);
+            }
+        }
+    }
+
+    /// every global position of the grid, row by row from the bottom
+    pub fn positions(&self) -> impl Iterator<Item = IVec2> + '_ {
+        let size = self.size().as_ivec2();
+        (0..size.y).flat_map(move |y| (0..size.x).map(move |x| self.anchor + IVec2::new(x, y)))
+    }
+
+    fn to_local(&self, point: IVec2) -> Option<UVec2> {
+        let local = point - self.anchor;
+        let size = self.size().as_ivec2();
+        if local.x < 0 || local.y < 0 || local.x >= size.x || local.y >= size.y {
+            return None;
+        }
+        Some(local.as_uvec2())
+    }
+}
+
+/// builds the unified world grid after postprocessing, if it is enabled in the worldgen settings
+pub fn build_world_tiles(map: &mut MapResource, worldgen: &WorldgenSettings) {
+    let world_tiles = if worldgen.unified_world_grid {
+        WorldTiles::from_map_area(map.map_area())
+    } else {
+        None
+    };
+    map.map_area_mut().world_tiles = world_tiles;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::room::Room;
+
+    #[test]
+    fn test_double_walls_are_merged() {
+        let mut map_area = MapResource::default().map_area().clone();
+        for room in [
+            Room::new(0, 5, 5, IVec2::new(0, 0), true),
+            Room::new(1, 5, 5, IVec2::new(5, 0), true),
+        ] {
+            map_area.rooms.insert(room.id(), room);
+        }
+        for room in map_area.rooms.values_mut() {
+            room.fill_edges();
+        }
+        // a door that was carved on one side only
+        map_area
+            .rooms
+            .get_mut(&0)
+            .unwrap()
+            .set_tile(UVec2::new(4, 1), Tile::Ground);
+
+        let world_tiles = WorldTiles::from_map_area(&map_area).unwrap();
+        assert!(world_tiles.size() == UVec2::new(10, 5));
+        // room 0 keeps its wall, the wall of room 1 is opened up
+        assert!(world_tiles.get(IVec2::new(4, 2)) == Some(Tile::Wall));
+        assert!(world_tiles.is_walkable(IVec2::new(5, 2)));
+        assert!(world_tiles.is_walkable(IVec2::new(5, 3)));
+        // corners stay
+        assert!(world_tiles.get(IVec2::new(5, 0)) == Some(Tile::Wall));
+        assert!(world_tiles.get(IVec2::new(5, 4)) == Some(Tile::Wall));
+        // the one sided door stays closed
+        assert!(world_tiles.get(IVec2::new(5, 1)) == Some(Tile::Wall));
+        // the per room grids are not changed
+        assert!(map_area.global_tile(IVec2::new(5, 2)) == Some(&Tile::Wall));
+        assert!(world_tiles.get(IVec2::new(10, 0)).is_none());
+    }
+}